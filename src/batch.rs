@@ -0,0 +1,244 @@
+use std::error::Error;
+use std::ffi::OsString;
+use std::io::Read;
+use std::path::Path;
+
+use wayland_client::EventQueue;
+
+use crate::client::Flow;
+use crate::commands::{self, Reply};
+use crate::config::Config;
+use crate::options::{Arguments, Options, parse_from};
+
+/// Separates chained commands on the command line
+pub const SEPARATOR: &str = ";";
+
+/// Read one command per line from a file or stdin. Empty lines and lines starting with # are skipped.
+/// Arguments are separated by whitespace, which can be kept in an argument by quoting it like in a shell.
+pub fn read_steps(file: Option<&Path>) -> Result<Vec<Vec<OsString>>, Box<dyn Error>> {
+    let input = match file {
+        Some(path) => std::fs::read_to_string(path)
+            .map_err(|error| format!("Failed to read {}: {}", path.display(), error))?,
+        None => {
+            let mut input = String::new();
+            std::io::stdin().read_to_string(&mut input)?;
+            input
+        }
+    };
+
+    input
+        .lines()
+        .map(str::trim)
+        .enumerate()
+        .filter(|(_, line)| !line.is_empty() && !line.starts_with('#'))
+        .map(|(index, line)| {
+            split_line(line).map_err(|error| format!("Line {}: {}", index + 1, error).into())
+        })
+        .collect()
+}
+
+/// Split a line into arguments. Single quotes keep everything as is, while a backslash escapes
+/// the next character outside of quotes and a quote or backslash within double quotes.
+fn split_line(line: &str) -> Result<Vec<OsString>, String> {
+    let mut args = vec![];
+    let mut arg: Option<String> = None;
+    let mut chars = line.chars();
+
+    while let Some(character) = chars.next() {
+        match character {
+            character if character.is_whitespace() => {
+                args.extend(arg.take().map(OsString::from));
+            }
+            '\'' => {
+                let arg = arg.get_or_insert_with(String::new);
+                loop {
+                    match chars.next() {
+                        Some('\'') => break,
+                        Some(character) => arg.push(character),
+                        None => return Err(String::from("Missing closing quote")),
+                    }
+                }
+            }
+            '"' => {
+                let arg = arg.get_or_insert_with(String::new);
+                loop {
+                    match chars.next() {
+                        Some('"') => break,
+                        Some('\\') => match chars.next() {
+                            Some(character @ ('"' | '\\')) => arg.push(character),
+                            Some(character) => {
+                                arg.push('\\');
+                                arg.push(character);
+                            }
+                            None => return Err(String::from("Missing closing quote")),
+                        },
+                        Some(character) => arg.push(character),
+                        None => return Err(String::from("Missing closing quote")),
+                    }
+                }
+            }
+            '\\' => match chars.next() {
+                Some(character) => arg.get_or_insert_with(String::new).push(character),
+                None => return Err(String::from("Nothing to escape at the end of the line")),
+            },
+            character => arg.get_or_insert_with(String::new).push(character),
+        }
+    }
+
+    args.extend(arg.map(OsString::from));
+    Ok(args)
+}
+
+/// Separates the commands of a batch forwarded to the daemon. Unlike ;, it can't be taken for an argument passed on to river.
+const FORWARDED_SEPARATOR: &str = "\u{1e}";
+
+/// Whether the arguments chain several commands. A ; after -- is passed on to river instead.
+pub fn is_chained(args: &[OsString]) -> bool {
+    args.iter().any(|arg| arg == FORWARDED_SEPARATOR)
+        || args
+            .iter()
+            .take_while(|arg| *arg != "--")
+            .any(|arg| arg == SEPARATOR)
+}
+
+/// Split chained arguments into the options of the whole batch and the arguments of each command.
+/// Global options before the first command and batch along with its options are taken for the batch.
+/// Everything after -- belongs to the last command.
+pub fn split_steps(args: &[OsString]) -> (Vec<OsString>, Vec<Vec<OsString>>) {
+    if args.iter().any(|arg| arg == FORWARDED_SEPARATOR) {
+        let mut parts = args.split(|arg| arg == FORWARDED_SEPARATOR);
+        // Splitting always leaves at least one part
+        let options = parts.next().unwrap().to_vec();
+        return (options, parts.map(<[OsString]>::to_vec).collect());
+    }
+
+    let end = args
+        .iter()
+        .position(|arg| arg == "--")
+        .unwrap_or(args.len());
+    let mut steps: Vec<Vec<OsString>> = args[..end]
+        .split(|arg| arg == SEPARATOR)
+        .map(<[OsString]>::to_vec)
+        .collect();
+    steps.last_mut().unwrap().extend_from_slice(&args[end..]);
+
+    let leading = leading_options(&steps[0]);
+    let first = steps[0].split_off(leading);
+    let options = std::mem::replace(&mut steps[0], first);
+    (options, steps)
+}
+
+/// The number of arguments at the start of the first command that are meant for the whole batch
+fn leading_options(step: &[OsString]) -> usize {
+    let mut count = 0;
+    loop {
+        match step.get(count).and_then(|arg| arg.to_str()) {
+            Some("--output" | "--seat") => count += 2,
+            Some("--dry-run" | "--explain") => count += 1,
+            _ => break,
+        }
+    }
+    if step.get(count).is_some_and(|arg| arg == "batch") {
+        count += 1;
+        if step.get(count).is_some_and(|arg| arg == "--stop-on-error") {
+            count += 1;
+        }
+    }
+
+    count.min(step.len())
+}
+
+/// The arguments to run a batch with, as forwarded to the daemon
+pub fn to_args(
+    steps: &[Vec<OsString>],
+    output: Option<&str>,
+    seat: Option<&str>,
+    flags: &[&str],
+    stop_on_error: bool,
+) -> Vec<OsString> {
+    let mut args = vec![];
+    for (option, value) in [("--output", output), ("--seat", seat)] {
+        if let Some(value) = value {
+            args.extend([OsString::from(option), OsString::from(value)]);
+        }
+    }
+    args.extend(flags.iter().map(OsString::from));
+
+    args.push(OsString::from("batch"));
+    if stop_on_error {
+        args.push(OsString::from("--stop-on-error"));
+    }
+    for step in steps {
+        args.push(OsString::from(FORWARDED_SEPARATOR));
+        args.extend(step.iter().cloned());
+    }
+    args
+}
+
+/// Run every command in order against the same connection.
+/// Nothing is run if any of the commands can't be parsed.
+pub fn run(
+    flow: &mut Flow,
+    event_queue: &mut EventQueue<Flow>,
+    config: &Config,
+    batch: Options,
+) -> Reply {
+    let Arguments::Batch {
+        steps,
+        stop_on_error,
+        ..
+    } = batch.command
+    else {
+        return Reply::from(Err("Not a batch".into()));
+    };
+
+    let mut parsed = vec![];
+    for (number, step) in steps.into_iter().enumerate() {
+        match parse_step(step, config) {
+            Ok(mut options) => {
+                // Options given to the batch apply to every command that doesn't have its own
+                options.output = options.output.or_else(|| batch.output.clone());
+                options.seat = options.seat.or_else(|| batch.seat.clone());
+                options.dry_run |= batch.dry_run;
+                options.explain |= batch.explain;
+                parsed.push(options);
+            }
+            Err(error) => {
+                return Reply::from(Err(format!("Command {}: {}", number + 1, error).into()));
+            }
+        }
+    }
+
+    let mut reply = Reply::from(Ok(String::new()));
+    for options in parsed {
+        // A dry run of the whole batch puts the state back once, so each command sees what the one before would leave
+        let step = match batch.dry_run {
+            true => commands::run(flow, event_queue, config, options),
+            false => commands::execute(flow, event_queue, config, options),
+        };
+
+        reply.stdout.push_str(&step.stdout);
+        reply.stderr.push_str(&step.stderr);
+        if step.code != 0 {
+            reply.code = step.code;
+            if stop_on_error {
+                break;
+            }
+        }
+    }
+
+    reply
+}
+
+fn parse_step(step: Vec<OsString>, config: &Config) -> Result<Options, Box<dyn Error>> {
+    let options = parse_from(step, config)?;
+
+    match options.command {
+        Arguments::Global { .. } => Err("Missing command".into()),
+        Arguments::Batch { .. } => Err("A batch can't run another batch".into()),
+        Arguments::Watch { .. } | Arguments::Daemon { .. } => {
+            Err("watch and daemon can't be run in a batch".into())
+        }
+        _ => Ok(options),
+    }
+}
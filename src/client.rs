@@ -1,21 +1,31 @@
+use std::time::Instant;
+
 use wayland_client::{
     Connection, Dispatch, Proxy, QueueHandle,
     backend::ObjectId,
     protocol::{
+        wl_callback::WlCallback,
         wl_output::{Event::Name, WlOutput},
-        wl_registry::{Event::Global, WlRegistry},
-        wl_seat::WlSeat,
+        wl_registry::{
+            Event::{Global, GlobalRemove},
+            WlRegistry,
+        },
+        wl_seat::{self, WlSeat},
     },
 };
 
 use crate::output::Output;
 use crate::seat::Seat;
+use crate::tags::TagMask;
 
 use crate::protocols::river_protocols::{
-    zriver_command_callback_v1::ZriverCommandCallbackV1,
+    zriver_command_callback_v1::{
+        Event::{Failure, Success},
+        ZriverCommandCallbackV1,
+    },
     zriver_control_v1::ZriverControlV1,
     zriver_output_status_v1::{
-        Event::{FocusedTags, UrgentTags, ViewTags},
+        Event::{FocusedTags, LayoutName, LayoutNameClear, UrgentTags, ViewTags},
         ZriverOutputStatusV1,
     },
     zriver_seat_status_v1::{self, ZriverSeatStatusV1},
@@ -25,28 +35,124 @@ use crate::protocols::river_protocols::{
 #[derive(Debug)]
 pub struct Flow {
     pub status_manager: Option<zriver_status_manager_v1::ZriverStatusManagerV1>,
-    pub seat: Option<Seat>,
+    /// Every seat, in the order river advertised them
+    pub seats: Vec<Seat>,
+    /// The seat commands are run on and whose focus is used, the first seat if None
+    pub seat_name: Option<String>,
     pub outputs: Vec<Output>,
     pub control: Option<ZriverControlV1>,
+    /// Queue the layout remembered for tags when they're focused, only done by the daemon
+    pub restore_layouts: bool,
+    /// The last jump to urgent tags, for focus-urgent-tags --return
+    pub urgent_return: Option<UrgentJump>,
+    /// What river answered to each command sent, None until the callback has fired
+    pub command_results: Vec<Option<Result<String, String>>>,
+}
+
+/// Where focus-urgent-tags jumped from and to
+#[derive(Clone, Debug)]
+pub struct UrgentJump {
+    /// The output and tags focused before the first of a series of jumps
+    pub from: (String, TagMask),
+    /// The output and tags of the last jump. While they are focused, another jump is part of the same series.
+    pub to: (String, TagMask),
 }
 
 impl Flow {
     pub fn new() -> Self {
         Self {
             status_manager: None,
-            seat: None,
+            seats: vec![],
+            seat_name: None,
             outputs: vec![],
             control: None,
+            restore_layouts: false,
+            urgent_return: None,
+            command_results: vec![],
         }
     }
 
-    /// Send a command to river
-    pub fn send_command(&self, arguments: Vec<String>, queue_handle: &QueueHandle<Self>) {
-        if let (Some(control), Some(seat)) = (&self.control, &self.seat) {
+    /// Send a command to river. The outcome ends up in `command_results` once the callback has fired.
+    pub fn send_command(&mut self, arguments: Vec<String>, queue_handle: &QueueHandle<Self>) {
+        if let (Some(control), Some(seat)) = (&self.control, self.seat()) {
             for arg in &arguments {
                 control.add_argument(arg.to_owned());
             }
-            control.run_command(&seat.wlseat, queue_handle, ());
+            control.run_command(&seat.wlseat, queue_handle, self.command_results.len());
+            self.command_results.push(None);
+        }
+    }
+
+    /// The selected seat
+    pub fn seat(&self) -> Option<&Seat> {
+        match &self.seat_name {
+            Some(name) => self
+                .seats
+                .iter()
+                .find(|seat| seat.name.as_ref() == Some(name)),
+            None => self.seats.first(),
+        }
+    }
+
+    /// The selected seat, to update its state
+    pub fn seat_mut(&mut self) -> Option<&mut Seat> {
+        match &self.seat_name {
+            Some(name) => self
+                .seats
+                .iter_mut()
+                .find(|seat| seat.name.as_ref() == Some(name)),
+            None => self.seats.first_mut(),
+        }
+    }
+
+    /// Select the seat to use by name, or the first seat if None
+    pub fn select_seat(&mut self, name: Option<&str>) -> Result<(), String> {
+        self.seat_name = name.map(String::from);
+
+        match (name, self.seat()) {
+            (Some(name), None) => Err(format!("There is no seat named {}", name)),
+            _ => Ok(()),
+        }
+    }
+
+    /// Whether the output is focused by the selected seat
+    pub fn is_focused(&self, output: &Output) -> bool {
+        self.seat()
+            .and_then(|seat| seat.focused_output.as_ref())
+            .is_some_and(|id| *id == output.wloutput.id())
+    }
+
+    /// Bind a status object for every seat and output that doesn't have one yet.
+    /// Outputs can be added while the daemon is running, so this is called after each dispatch.
+    pub fn bind_status(&mut self, queue_handle: &QueueHandle<Self>) {
+        let Some(status_manager) = self.status_manager.as_ref() else {
+            return;
+        };
+
+        // Pass on the wlseat id so the focus is tracked for the right seat
+        for seat in self
+            .seats
+            .iter_mut()
+            .filter(|seat| seat.seat_status.is_none())
+        {
+            seat.seat_status = Some(status_manager.get_river_seat_status(
+                &seat.wlseat,
+                queue_handle,
+                seat.wlseat.id(),
+            ));
+        }
+
+        // Pass on the wloutput id so we bind the state correctly to each output
+        for output in self
+            .outputs
+            .iter_mut()
+            .filter(|output| output.status.is_none())
+        {
+            output.status = Some(status_manager.get_river_output_status(
+                &output.wloutput,
+                queue_handle,
+                output.wloutput.id(),
+            ));
         }
     }
 
@@ -62,8 +168,10 @@ impl Flow {
             }
         }
 
-        if let Some(status) = self.seat.as_mut().and_then(|seat| seat.seat_status.take()) {
-            status.destroy()
+        for seat in &mut self.seats {
+            if let Some(status) = seat.seat_status.take() {
+                status.destroy()
+            }
         }
 
         if let Some(control) = self.control.take() {
@@ -74,15 +182,44 @@ impl Flow {
     /// Identify an output based on a specific state
     pub fn find_output(&self, state: &str) -> Option<&Output> {
         match state {
-            "focused" => self.outputs.iter().find(|output| output.focused),
-            "urgent" => self
+            "focused" => self.outputs.iter().find(|output| self.is_focused(output)),
+            "urgent" => self.urgent_outputs().into_iter().next(),
+            &_ => None,
+        }
+    }
+
+    /// Every output with urgent tags, the one that has been urgent the longest first
+    pub fn urgent_outputs(&self) -> Vec<&Output> {
+        let mut outputs: Vec<&Output> = self
+            .outputs
+            .iter()
+            .filter(|output| output.urgent_tags.is_some())
+            .collect();
+        outputs.sort_by_key(|output| output.urgent_since);
+        outputs
+    }
+
+    /// The output a command should act on, either the one asked for by name or the focused output
+    pub fn target_output(&self, name: Option<&str>) -> Result<&Output, String> {
+        match name {
+            Some(name) => self
                 .outputs
                 .iter()
-                .find(|output| output.urgent_tags.is_some()),
-            &_ => None,
+                .find(|output| output.name == name)
+                .ok_or_else(|| format!("There is no output named {}", name)),
+            None => self
+                .find_output("focused")
+                .ok_or_else(|| String::from("There is no focused output")),
         }
     }
 
+    /// Get a mutable seat matching the wlseat id. This is used to update state.
+    pub fn get_seat(&mut self, wlseat_id: &ObjectId) -> Option<&mut Seat> {
+        self.seats
+            .iter_mut()
+            .find(|seat| seat.wlseat.id() == *wlseat_id)
+    }
+
     /// Get a mutable output matching the wloutput id. This is used to update state.
     pub fn get_output(&mut self, wloutput_id: &ObjectId) -> Option<&mut Output> {
         self.outputs
@@ -100,7 +237,20 @@ impl Dispatch<WlRegistry, ()> for Flow {
         _: &Connection,
         queue_handle: &QueueHandle<Self>,
     ) {
-        if let Global {
+        if let GlobalRemove { name } = event {
+            // Only outputs are expected to go away while flow is running
+            if let Some(index) = state
+                .outputs
+                .iter()
+                .position(|output| output.global_name == name)
+            {
+                let output = state.outputs.remove(index);
+                if let Some(status) = output.status {
+                    status.destroy();
+                }
+                output.wloutput.release();
+            }
+        } else if let Global {
             name,
             interface,
             version,
@@ -108,7 +258,8 @@ impl Dispatch<WlRegistry, ()> for Flow {
         {
             match interface.as_str() {
                 "wl_output" => {
-                    registry.bind::<WlOutput, _, Self>(name, version, queue_handle, ());
+                    // Keep the global name around so the output can be removed again
+                    registry.bind::<WlOutput, _, Self>(name, version, queue_handle, name);
                 }
                 "zriver_status_manager_v1" => {
                     state.status_manager = Some(registry.bind::<ZriverStatusManagerV1, _, Self>(
@@ -127,7 +278,7 @@ impl Dispatch<WlRegistry, ()> for Flow {
                     ));
                 }
                 "wl_seat" => {
-                    state.seat = Some(Seat::new(registry.bind::<WlSeat, _, Self>(
+                    state.seats.push(Seat::new(registry.bind::<WlSeat, _, Self>(
                         name,
                         version,
                         queue_handle,
@@ -156,63 +307,114 @@ impl Dispatch<ZriverOutputStatusV1, ObjectId> for Flow {
                 }
             }
             FocusedTags { tags } => {
+                let restore_layouts = state.restore_layouts;
                 if let Some(output) = state.get_output(wloutput_id) {
-                    output.focused_tags = Some(tags);
+                    output.focus_tags(TagMask::new(tags), restore_layouts);
                 }
             }
             UrgentTags { tags } => {
-                // If urgent tags are not 0 (e.g. none are urgent), we add the tags
-                if tags != 0
-                    && let Some(output) = state.get_output(wloutput_id) {
-                        output.urgent_tags = Some(tags);
+                if let Some(output) = state.get_output(wloutput_id) {
+                    // No urgent tags are reported as 0
+                    if tags == 0 {
+                        output.urgent_tags = None;
+                        output.urgent_since = None;
+                    } else {
+                        output.urgent_tags = Some(TagMask::new(tags));
+                        output.urgent_since.get_or_insert_with(Instant::now);
+                    }
+                }
+            }
+            LayoutName { name } => {
+                if let Some(output) = state.get_output(wloutput_id) {
+                    if let Some(tags) = output.focused_tags {
+                        output.layouts.insert(tags, name.to_owned());
                     }
+                    output.layout_name = Some(name);
+                }
+            }
+            LayoutNameClear => {
+                if let Some(output) = state.get_output(wloutput_id) {
+                    output.layout_name = None;
+                }
             }
-            _ => (),
         }
     }
 }
 
-impl Dispatch<ZriverSeatStatusV1, ()> for Flow {
+impl Dispatch<ZriverSeatStatusV1, ObjectId> for Flow {
     fn event(
         state: &mut Self,
         _: &ZriverSeatStatusV1,
         event: <ZriverSeatStatusV1 as Proxy>::Event,
-        _: &(),
+        wlseat_id: &ObjectId,
         _: &Connection,
         _: &QueueHandle<Self>,
     ) {
-        if let zriver_seat_status_v1::Event::FocusedOutput { output } = event
-            && let Some(output) = state.get_output(&output.id()) {
-                output.focused = true;
+        let Some(seat) = state.get_seat(wlseat_id) else {
+            return;
+        };
+
+        match event {
+            zriver_seat_status_v1::Event::FocusedOutput { output } => {
+                seat.focused_output = Some(output.id());
             }
+            zriver_seat_status_v1::Event::UnfocusedOutput { output } => {
+                if seat.focused_output == Some(output.id()) {
+                    seat.focused_output = None;
+                }
+            }
+            zriver_seat_status_v1::Event::FocusedView { title } => {
+                seat.focused_view = Some(title);
+            }
+            zriver_seat_status_v1::Event::Mode { name } => {
+                seat.mode = Some(name);
+            }
+        }
     }
 }
 
-impl Dispatch<WlOutput, ()> for Flow {
+impl Dispatch<WlOutput, u32> for Flow {
     fn event(
         state: &mut Self,
         wloutput: &WlOutput,
         event: <WlOutput as Proxy>::Event,
-        _: &(),
+        global_name: &u32,
         _: &Connection,
         _: &QueueHandle<Self>,
     ) {
         if let Name { name } = event {
-            let output = Output::new(name, wloutput.to_owned());
+            let output = Output::new(name, *global_name, wloutput.to_owned());
             state.outputs.push(output);
         }
     }
 }
 
-impl Dispatch<WlSeat, ()> for Flow {
+impl Dispatch<WlCallback, ()> for Flow {
     fn event(
         _: &mut Self,
-        _: &WlSeat,
-        _: <WlSeat as Proxy>::Event,
+        _: &WlCallback,
+        _: <WlCallback as Proxy>::Event,
+        _: &(),
+        _: &Connection,
+        _: &QueueHandle<Self>,
+    ) {
+    }
+}
+
+impl Dispatch<WlSeat, ()> for Flow {
+    fn event(
+        state: &mut Self,
+        wlseat: &WlSeat,
+        event: <WlSeat as Proxy>::Event,
         _: &(),
         _: &Connection,
         _: &QueueHandle<Self>,
     ) {
+        if let wl_seat::Event::Name { name } = event
+            && let Some(seat) = state.get_seat(&wlseat.id())
+        {
+            seat.name = Some(name);
+        }
     }
 }
 
@@ -228,15 +430,23 @@ impl Dispatch<ZriverStatusManagerV1, ()> for Flow {
     }
 }
 
-impl Dispatch<ZriverCommandCallbackV1, ()> for Flow {
+impl Dispatch<ZriverCommandCallbackV1, usize> for Flow {
     fn event(
-        _: &mut Self,
+        state: &mut Self,
         _: &ZriverCommandCallbackV1,
-        _: <ZriverCommandCallbackV1 as Proxy>::Event,
-        _: &(),
+        event: <ZriverCommandCallbackV1 as Proxy>::Event,
+        index: &usize,
         _: &Connection,
         _: &QueueHandle<Self>,
     ) {
+        let outcome = match event {
+            Success { output } => Ok(output),
+            Failure { failure_message } => Err(failure_message),
+        };
+
+        if let Some(result) = state.command_results.get_mut(*index) {
+            *result = Some(outcome);
+        }
     }
 }
 
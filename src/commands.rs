@@ -0,0 +1,613 @@
+use std::error::Error;
+
+use wayland_client::{EventQueue, Proxy, QueueHandle};
+
+use crate::batch;
+use crate::client::{Flow, UrgentJump};
+use crate::config::Config;
+use crate::options::{Arguments, Options, ScratchpadAction};
+use crate::output::Output;
+use crate::query;
+use crate::tags::{Direction, TagFilter, TagMask};
+
+/// The outcome of a command, either printed directly or sent back from the daemon to a client
+pub struct Reply {
+    pub code: i32,
+    pub stdout: String,
+    pub stderr: String,
+}
+
+impl From<Result<String, Box<dyn Error>>> for Reply {
+    fn from(result: Result<String, Box<dyn Error>>) -> Self {
+        match result {
+            Ok(stdout) => Self {
+                code: 0,
+                stdout,
+                stderr: String::new(),
+            },
+            Err(error) => Self {
+                code: 1,
+                stdout: String::new(),
+                stderr: format!("Error: {}\n", error),
+            },
+        }
+    }
+}
+
+impl Reply {
+    /// Print the reply as if the command had been run in this process
+    pub fn print(&self) {
+        print!("{}", self.stdout);
+        eprint!("{}", self.stderr);
+    }
+}
+
+/// Run a parsed command and collect what river answered to each river command that was sent
+pub fn execute(
+    flow: &mut Flow,
+    event_queue: &mut EventQueue<Flow>,
+    config: &Config,
+    options: Options,
+) -> Reply {
+    // A dry run takes its commands as run, so the next command of a batch is worked out from the state they
+    // would leave. Nothing was run though, so the state is put back afterwards.
+    let saved = options
+        .dry_run
+        .then(|| (flow.outputs.clone(), flow.seats.clone()));
+
+    let reply = run(flow, event_queue, config, options);
+
+    if let Some((outputs, seats)) = saved {
+        flow.outputs = outputs;
+        flow.seats = seats;
+    }
+
+    reply
+}
+
+/// Run a parsed command like execute, but keep the state a dry run leaves
+pub fn run(
+    flow: &mut Flow,
+    event_queue: &mut EventQueue<Flow>,
+    config: &Config,
+    options: Options,
+) -> Reply {
+    if let Arguments::Batch { .. } = options.command {
+        return batch::run(flow, event_queue, config, options);
+    }
+
+    let mut reply = Reply::from(handle(flow, event_queue, config, options));
+
+    for result in flow.command_results.drain(..) {
+        match result {
+            Some(Ok(output)) if !output.is_empty() => {
+                reply.stdout.push_str(&output);
+                if !output.ends_with('\n') {
+                    reply.stdout.push('\n');
+                }
+            }
+            Some(Ok(_)) => (),
+            Some(Err(failure_message)) => {
+                reply.code = 1;
+                reply
+                    .stderr
+                    .push_str(&format!("Error: {}\n", failure_message));
+            }
+            None => {
+                reply.code = 1;
+                reply
+                    .stderr
+                    .push_str("Error: River did not answer a command\n");
+            }
+        }
+    }
+
+    reply
+}
+
+/// The river commands that make up a flow command
+pub struct Plan {
+    /// The commands are run with this output focused, and focus is given back afterwards.
+    /// None if the commands take care of focus themselves.
+    pub output: Option<String>,
+    pub commands: Vec<Vec<String>>,
+}
+
+/// Run a parsed command against the current state and send the resulting river commands.
+/// Returns whatever should be printed to stdout.
+fn handle(
+    flow: &mut Flow,
+    event_queue: &mut EventQueue<Flow>,
+    config: &Config,
+    options: Options,
+) -> Result<String, Box<dyn Error>> {
+    // The daemon serves every seat, so the seat is picked again for each command
+    flow.select_seat(options.seat.as_deref())?;
+
+    // Querying only reads the state, there's nothing to send
+    if let Arguments::Query { json, format } = &options.command {
+        return Ok(query::snapshot(
+            flow,
+            config,
+            options.output.as_deref(),
+            *json,
+            format.as_ref(),
+        )?);
+    }
+
+    let plan = plan(flow, config, &options)?;
+    let commands = with_focus(flow, plan);
+
+    let mut stdout = String::new();
+    if options.explain {
+        stdout.push_str(&explain(flow, config, &options));
+    }
+    if options.explain || options.dry_run {
+        for command in &commands {
+            stdout.push_str(&format_command(command));
+            stdout.push('\n');
+        }
+    }
+    if options.dry_run {
+        update_focus(flow, &commands);
+        return Ok(stdout);
+    }
+
+    let first_result = flow.command_results.len();
+    for command in &commands {
+        flow.send_command(command.to_owned(), &event_queue.handle());
+    }
+    event_queue.roundtrip(flow)?;
+
+    let results = flow.command_results[first_result..].to_vec();
+    let succeeded: Vec<Vec<String>> = commands
+        .into_iter()
+        .zip(results)
+        .filter_map(|(command, result)| matches!(result, Some(Ok(_))).then_some(command))
+        .collect();
+    update_focus(flow, &succeeded);
+
+    Ok(stdout)
+}
+
+/// River reports newly focused tags once they're shown, which can be after it has answered the command.
+/// Take the output and tags focused by the commands as focused right away, so the next command of a batch sees them.
+fn update_focus(flow: &mut Flow, commands: &[Vec<String>]) {
+    let mut focused = flow
+        .find_output("focused")
+        .map(|output| output.name.to_owned());
+
+    for command in commands {
+        match command.as_slice() {
+            [name, output] if name == "focus-output" => focused = Some(output.to_owned()),
+            [name, tags] if name == "set-focused-tags" => {
+                let restore_layouts = flow.restore_layouts;
+                if let Some(name) = &focused
+                    && let Some(output) =
+                        flow.outputs.iter_mut().find(|output| &output.name == name)
+                    && let Ok(tags) = tags.parse()
+                {
+                    output.focus_tags(TagMask::new(tags), restore_layouts);
+                }
+            }
+            _ => (),
+        }
+    }
+
+    let focused_id = focused
+        .and_then(|name| flow.outputs.iter().find(|output| output.name == name))
+        .map(|output| output.wloutput.id());
+    if let Some(id) = focused_id
+        && let Some(seat) = flow.seat_mut()
+    {
+        seat.focused_output = Some(id);
+    }
+}
+
+/// Send the commands of a plan
+pub fn send(flow: &mut Flow, queue_handle: &QueueHandle<Flow>, plan: Plan) {
+    for command in with_focus(flow, plan) {
+        flow.send_command(command, queue_handle);
+    }
+}
+
+/// The commands of a plan, focusing its output first if it isn't already
+/// and going back to the originally focused output after
+fn with_focus(flow: &Flow, plan: Plan) -> Vec<Vec<String>> {
+    let mut commands = plan.commands;
+
+    if let Some(output) = plan.output
+        && let Some(focused) = flow.find_output("focused")
+        && focused.name != output
+        && !commands.is_empty()
+    {
+        commands.insert(0, vec![String::from("focus-output"), output]);
+        commands.push(vec![String::from("focus-output"), focused.name.to_owned()]);
+    }
+
+    commands
+}
+
+/// The state a command is worked out from, one value per line
+fn explain(flow: &Flow, config: &Config, options: &Options) -> String {
+    let focused = flow.find_output("focused");
+    let target = flow.target_output(options.output.as_deref()).ok();
+
+    // cycle-tags and cycle-view-tags can be given the number of tags on the command line
+    let n_tags = match &options.command {
+        Arguments::CycleTags {
+            n_tags: Some(n_tags),
+            ..
+        }
+        | Arguments::CycleViewTags {
+            n_tags: Some(n_tags),
+            ..
+        } => Some(*n_tags),
+        _ => target.map(|output| config.tag_count(&output.name)),
+    };
+
+    // Missing state is shown as -, like in query
+    let value = |value: Option<String>| value.unwrap_or_else(|| String::from("-"));
+    let tags = |tags: fn(&Output) -> Option<TagMask>| {
+        value(target.map(|output| tags(output).unwrap_or_default().to_string()))
+    };
+
+    format!(
+        "Focused output: {}\nOutput: {}\nFocused tags: {}\nOccupied tags: {}\nUrgent tags: {}\nNumber of tags: {}\nCommands:\n",
+        value(focused.map(|output| output.name.to_owned())),
+        value(target.map(|output| output.name.to_owned())),
+        tags(|output| output.focused_tags),
+        tags(|output| Some(output.occupied_mask())),
+        tags(|output| output.urgent_tags),
+        value(n_tags.map(|n_tags| (n_tags.last() + 1).to_string())),
+    )
+}
+
+/// A river command as it would be typed for riverctl, quoting arguments that need it
+fn format_command(command: &[String]) -> String {
+    command
+        .iter()
+        .map(|arg| {
+            if arg.is_empty() || arg.contains(|c: char| c.is_whitespace() || c == '\'') {
+                format!("'{}'", arg.replace('\'', r"'\''"))
+            } else {
+                arg.to_owned()
+            }
+        })
+        .collect::<Vec<String>>()
+        .join(" ")
+}
+
+/// Work out the river commands for a command from the current state, without sending anything
+fn plan(flow: &mut Flow, config: &Config, options: &Options) -> Result<Plan, Box<dyn Error>> {
+    let target = || flow.target_output(options.output.as_deref());
+    let mut commands = vec![];
+
+    let output = match &options.command {
+        Arguments::CycleTags {
+            direction,
+            n_tags,
+            skip_unoccupied,
+            steps,
+            wrap,
+        } => {
+            let output = target()?;
+
+            // Fall back to the config for anything not given on the command line
+            let n_tags = n_tags.unwrap_or_else(|| config.tag_count(&output.name));
+            let skip_unoccupied = skip_unoccupied.unwrap_or(config.cycle_tags.occupied);
+
+            let filter = match skip_unoccupied {
+                true => TagFilter::Occupied,
+                false => TagFilter::All,
+            };
+            let new_tags = output.cycle_tags(*direction, n_tags, filter, *steps, *wrap)?;
+
+            commands.push(vec![String::from("set-focused-tags"), new_tags.to_string()]);
+            Some(output.name.to_owned())
+        }
+        Arguments::CycleViewTags {
+            direction,
+            n_tags,
+            filter,
+            follow,
+            steps,
+            wrap,
+        } => {
+            let output = target()?;
+            let n_tags = n_tags.unwrap_or_else(|| config.tag_count(&output.name));
+
+            // The view is taken from the focused tags, so those are what's cycled
+            let new_tags = output.cycle_tags(*direction, n_tags, *filter, *steps, *wrap)?;
+
+            commands.push(vec![String::from("set-view-tags"), new_tags.to_string()]);
+            if *follow {
+                commands.push(vec![String::from("set-focused-tags"), new_tags.to_string()]);
+            }
+            Some(output.name.to_owned())
+        }
+        Arguments::ToggleTags { to_tags } => {
+            let output = target()?;
+            let to_tags = to_tags.resolve(Some(output), config.tag_count(&output.name))?;
+
+            if output.toggle_tags(to_tags) {
+                commands.push(vec![String::from("focus-previous-tags")]);
+            } else {
+                commands.push(vec![String::from("set-focused-tags"), to_tags.to_string()]);
+            }
+            Some(output.name.to_owned())
+        }
+        Arguments::FocusEmptyTag {
+            send_view,
+            after_current,
+        } => {
+            let output = target()?;
+            let n_tags = config.tag_count(&output.name);
+
+            let after = match after_current {
+                true => (output.focused_tags.unwrap_or_default() & n_tags.mask())
+                    .iter()
+                    .last(),
+                false => None,
+            };
+            let empty_tag = output
+                .find_empty_tag(n_tags, after)
+                .map(TagMask::single)
+                .ok_or("Every tag is occupied, there is no empty tag to focus")?;
+
+            if *send_view {
+                commands.push(vec![String::from("set-view-tags"), empty_tag.to_string()]);
+            }
+            commands.push(vec![
+                String::from("set-focused-tags"),
+                empty_tag.to_string(),
+            ]);
+            Some(output.name.to_owned())
+        }
+        Arguments::OccupiedTag {
+            position,
+            send_view,
+        } => {
+            let output = target()?;
+            let occupied_tags = output.find_set_bits_positions(config.tag_count(&output.name));
+
+            let tag = occupied_tags
+                .get(position - 1)
+                .map(|&index| TagMask::single(index))
+                .ok_or_else(|| {
+                    format!(
+                        "There is no occupied tag {}, only {} tags are occupied",
+                        position,
+                        occupied_tags.len()
+                    )
+                })?;
+
+            match send_view {
+                true => commands.push(vec![String::from("set-view-tags"), tag.to_string()]),
+                false => commands.push(vec![String::from("set-focused-tags"), tag.to_string()]),
+            }
+            Some(output.name.to_owned())
+        }
+        Arguments::SwapOutputTags { with } => {
+            let output = target()?;
+            let other = match with {
+                Some(name) => flow.target_output(Some(name))?,
+                None => {
+                    let others: Vec<&Output> = flow
+                        .outputs
+                        .iter()
+                        .filter(|other| other.name != output.name)
+                        .collect();
+                    match others[..] {
+                        [other] => other,
+                        [] => return Err("There is no other output to swap with".into()),
+                        _ => {
+                            return Err(
+                                "There are several other outputs, pick one with --with".into()
+                            );
+                        }
+                    }
+                }
+            };
+
+            if other.name == output.name {
+                return Err("An output can't swap tags with itself".into());
+            }
+            let (Some(tags), Some(other_tags)) = (output.focused_tags, other.focused_tags) else {
+                return Err("The focused tags of both outputs are needed to swap them".into());
+            };
+
+            // Focus ends up back on the output the command is run on
+            commands.push(vec![
+                String::from("set-focused-tags"),
+                other_tags.to_string(),
+            ]);
+            commands.push(vec![String::from("focus-output"), other.name.to_owned()]);
+            commands.push(vec![String::from("set-focused-tags"), tags.to_string()]);
+            commands.push(vec![String::from("focus-output"), output.name.to_owned()]);
+            Some(output.name.to_owned())
+        }
+        Arguments::FocusUrgentTags { jump_back: true } => {
+            let (name, tags) = flow
+                .urgent_return
+                .clone()
+                .ok_or("There is no jump to urgent tags to return from")?
+                .from;
+
+            // A dry run leaves the jump to be returned from for real
+            if !options.dry_run {
+                flow.urgent_return = None;
+            }
+
+            commands.push(vec![String::from("focus-output"), name]);
+            commands.push(vec![String::from("set-focused-tags"), tags.to_string()]);
+
+            // Focus is meant to stay where it was before the jump
+            None
+        }
+        Arguments::FocusUrgentTags { jump_back: false } => {
+            // Only the output asked for, or every urgent output
+            let urgent: Vec<&Output> = match &options.output {
+                Some(_) => Some(target()?)
+                    .filter(|output| output.urgent_tags.is_some())
+                    .into_iter()
+                    .collect(),
+                None => flow.urgent_outputs(),
+            };
+
+            // If the focused output is on its urgent tags it was jumped to before, so move on to the next one
+            let focused = flow.find_output("focused");
+            let current = urgent.iter().position(|output| {
+                Some(output.name.as_str()) == focused.map(|focused| focused.name.as_str())
+                    && output.focused_tags == output.urgent_tags
+            });
+            let next = match current {
+                Some(index) => urgent.get((index + 1) % urgent.len()),
+                None => urgent.first(),
+            };
+
+            let mut jump = None;
+            if let Some(output) = next
+                && let Some(urgent_tags) = output.urgent_tags
+                && let Some(focused) = focused
+            {
+                commands.push(vec![String::from("focus-output"), output.name.to_owned()]);
+                commands.push(vec![
+                    String::from("set-focused-tags"),
+                    urgent_tags.to_string(),
+                ]);
+
+                // Only the first jump is remembered, moving on to the next urgent output keeps it.
+                // River clears urgency once the view is focused, so the last jump target may no longer be urgent.
+                let focused = (
+                    focused.name.to_owned(),
+                    focused.focused_tags.unwrap_or_default(),
+                );
+                let from = match &flow.urgent_return {
+                    Some(last) if last.to == focused || current.is_some() => {
+                        Some(last.from.clone())
+                    }
+                    // Focused urgent tags without jumping there, so there's nothing to return to
+                    None if current.is_some() => None,
+                    _ => Some(focused),
+                };
+                jump = from.map(|from| UrgentJump {
+                    from,
+                    to: (output.name.to_owned(), urgent_tags),
+                });
+            }
+
+            if jump.is_some() && !options.dry_run {
+                flow.urgent_return = jump;
+            }
+
+            // Focus is meant to stay on the urgent output
+            None
+        }
+        Arguments::FocusSetViewTags { to_tags } => {
+            let output = target()?;
+            let to_tags = to_tags.resolve(Some(output), config.tag_count(&output.name))?;
+
+            commands.push(vec![String::from("set-view-tags"), to_tags.to_string()]);
+            commands.push(vec![String::from("set-focused-tags"), to_tags.to_string()]);
+            Some(output.name.to_owned())
+        }
+        Arguments::CycleLayout { direction, layouts } => {
+            let output = target()?;
+            let layout = output
+                .cycle_layout(*direction, layouts, config)
+                .ok_or("There are no layouts to cycle through")?;
+
+            commands.push(vec![String::from("output-layout"), layout.to_owned()]);
+            Some(output.name.to_owned())
+        }
+        Arguments::Scratchpad {
+            action: ScratchpadAction::Init,
+            ..
+        } => {
+            // New views get the focused tags masked with this, which always includes a scratchpad that is shown
+            let spawn_tagmask = !config.scratchpad_mask();
+            commands.push(vec![
+                String::from("spawn-tagmask"),
+                spawn_tagmask.to_string(),
+            ]);
+
+            // The spawn tagmask is the same for every output
+            None
+        }
+        Arguments::Scratchpad {
+            action: ScratchpadAction::Toggle,
+            name,
+        } => {
+            let output = target()?;
+            let scratchpad = config.scratchpad_tag(name.as_deref())?;
+            let focused_tags = output.focused_tags.unwrap_or_default();
+
+            if !(focused_tags & scratchpad).is_empty() {
+                // Focusing no tags at all isn't possible, so go back to whatever was focused before
+                match focused_tags - scratchpad {
+                    tags if tags.is_empty() => {
+                        commands.push(vec![String::from("focus-previous-tags")])
+                    }
+                    tags => commands.push(vec![String::from("set-focused-tags"), tags.to_string()]),
+                }
+            } else if !(output.occupied_mask() & scratchpad).is_empty() {
+                commands.push(vec![
+                    String::from("set-focused-tags"),
+                    (focused_tags | scratchpad).to_string(),
+                ]);
+            } else {
+                return Err("The scratchpad doesn't hold any views".into());
+            }
+
+            Some(output.name.to_owned())
+        }
+        Arguments::Scratchpad {
+            action: ScratchpadAction::Send,
+            name,
+        } => {
+            let output = target()?;
+            let scratchpad = config.scratchpad_tag(name.as_deref())?;
+
+            commands.push(vec![String::from("set-view-tags"), scratchpad.to_string()]);
+            Some(output.name.to_owned())
+        }
+        Arguments::Run { river_command } => {
+            commands.push(river_command.to_owned());
+
+            // Like riverctl, run works without any output unless one is asked for
+            match &options.output {
+                Some(_) => Some(target()?.name.to_owned()),
+                None => None,
+            }
+        }
+        Arguments::History { direction } => {
+            let name = target()?.name.to_owned();
+            let output = flow
+                .outputs
+                .iter_mut()
+                .find(|output| output.name == name)
+                .ok_or("The output went away")?;
+
+            // Only the daemon lives long enough to collect more than the current tags
+            let tags = match options.dry_run {
+                true => output.history.peek(*direction),
+                false => output.history.step(*direction),
+            };
+            let tags = tags.ok_or(match direction {
+                Direction::Previous => "There are no earlier tags to go back to",
+                Direction::Next => "There are no later tags to go forward to",
+            })?;
+
+            commands.push(vec![String::from("set-focused-tags"), tags.to_string()]);
+            Some(name)
+        }
+        Arguments::Global { .. }
+        | Arguments::Batch { .. }
+        | Arguments::Query { .. }
+        | Arguments::Watch { .. }
+        | Arguments::Daemon { .. } => None,
+    };
+
+    Ok(Plan { output, commands })
+}
@@ -0,0 +1,181 @@
+use std::collections::HashMap;
+use std::error::Error;
+use std::path::PathBuf;
+
+use serde::Deserialize;
+
+use crate::tags::{TagCount, TagMask};
+
+/// Settings read from $XDG_CONFIG_HOME/flow/config.toml. Anything given on the command line takes precedence.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields, rename_all = "kebab-case")]
+pub struct Config {
+    /// Number of tags in use on every output
+    pub tags: Option<TagCount>,
+    /// Names of the tags, in order starting from the first tag
+    pub tag_names: Vec<String>,
+    /// Settings for a specific output, keyed by output name
+    pub outputs: HashMap<String, OutputConfig>,
+    pub cycle_tags: CycleTagsConfig,
+    /// The tag number each named scratchpad keeps its views on
+    pub scratchpads: HashMap<String, u8>,
+    /// The namespace of the layout generator that shows each layout name
+    pub layout_namespaces: HashMap<String, String>,
+}
+
+/// The tag used for the scratchpad when none are configured, as suggested by the river wiki
+const DEFAULT_SCRATCHPAD_TAG: u8 = 21;
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields, rename_all = "kebab-case")]
+pub struct OutputConfig {
+    pub tags: Option<TagCount>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields, rename_all = "kebab-case")]
+pub struct CycleTagsConfig {
+    /// Only cycle through occupied tags
+    pub occupied: bool,
+}
+
+impl Config {
+    /// Read the config file. A missing file is not an error, the defaults are used instead.
+    pub fn load() -> Result<Self, Box<dyn Error>> {
+        let Some(path) = config_path() else {
+            return Ok(Self::default());
+        };
+
+        let contents = match std::fs::read_to_string(&path) {
+            Ok(contents) => contents,
+            Err(error) if error.kind() == std::io::ErrorKind::NotFound => {
+                return Ok(Self::default());
+            }
+            Err(error) => {
+                return Err(format!("Failed to read {}: {}", path.display(), error).into());
+            }
+        };
+
+        let config: Self = toml::from_str(&contents)
+            .map_err(|error| format!("Invalid config in {}: {}", path.display(), error))?;
+
+        config
+            .validate()
+            .map_err(|error| format!("Invalid config in {}: {}", path.display(), error))?;
+
+        Ok(config)
+    }
+
+    /// The number of tags for an output, falling back to the global setting and then to the default of 9
+    pub fn tag_count(&self, output: &str) -> TagCount {
+        self.outputs
+            .get(output)
+            .and_then(|output| output.tags)
+            .or(self.tags)
+            .unwrap_or_default()
+    }
+
+    /// The name of a tag, or its number if it has no name
+    pub fn tag_name(&self, index: u8) -> String {
+        self.tag_names
+            .get(usize::from(index))
+            .cloned()
+            .unwrap_or_else(|| (index + 1).to_string())
+    }
+
+    /// The namespace to pass to output-layout for a layout name, as layout generators can show
+    /// something else than their namespace, e.g. rivertile shows []=. Names that aren't configured are taken as is.
+    pub fn layout_namespace<'a>(&'a self, name: &'a str) -> &'a str {
+        self.layout_namespaces
+            .get(name)
+            .map_or(name, String::as_str)
+    }
+
+    /// The tag of a scratchpad. Without a name, the only configured scratchpad is used,
+    /// or the default tag if there are none.
+    pub fn scratchpad_tag(&self, name: Option<&str>) -> Result<TagMask, String> {
+        let tag = match name {
+            Some(name) => *self
+                .scratchpads
+                .get(name)
+                .ok_or_else(|| format!("Unknown scratchpad: {}", name))?,
+            None => match self.scratchpads.len() {
+                0 => DEFAULT_SCRATCHPAD_TAG,
+                1 => *self.scratchpads.values().next().unwrap(),
+                _ => {
+                    return Err(String::from(
+                        "There are several scratchpads, name the one to use",
+                    ));
+                }
+            },
+        };
+
+        Ok(TagMask::single(tag - 1))
+    }
+
+    /// The tags of every scratchpad
+    pub fn scratchpad_mask(&self) -> TagMask {
+        if self.scratchpads.is_empty() {
+            return TagMask::single(DEFAULT_SCRATCHPAD_TAG - 1);
+        }
+
+        self.scratchpads
+            .values()
+            .fold(TagMask::default(), |mask, &tag| {
+                mask | TagMask::single(tag - 1)
+            })
+    }
+
+    fn validate(&self) -> Result<(), String> {
+        if self.tag_names.len() > 32 {
+            return Err(String::from("There can't be more than 32 tag names"));
+        }
+
+        for (index, name) in self.tag_names.iter().enumerate() {
+            // Names are used in place of tags on the command line, so they can't look like anything else
+            if name.is_empty() || !name.starts_with(|c: char| c.is_alphabetic()) {
+                return Err(format!(
+                    "Tag names must start with a letter, got: {:?}",
+                    name
+                ));
+            }
+            if name.contains([',', '~', '+', '-', ':']) {
+                return Err(format!(
+                    "Tag names can't contain any of , ~ + - :, got: {}",
+                    name
+                ));
+            }
+            if ["focused", "occupied", "urgent", "mask"].contains(&name.as_str()) {
+                return Err(format!("The tag name {} is reserved", name));
+            }
+            if self.tag_names[..index].contains(name) {
+                return Err(format!("The tag name {} is used more than once", name));
+            }
+        }
+
+        let mut scratchpad_tags = TagMask::default();
+        for (name, &tag) in &self.scratchpads {
+            if !(1..=32).contains(&tag) {
+                return Err(format!(
+                    "The tag of scratchpad {} must be between 1 and 32, got {}",
+                    name, tag
+                ));
+            }
+            if scratchpad_tags.contains(tag - 1) {
+                return Err(format!("Tag {} is used by more than one scratchpad", tag));
+            }
+            scratchpad_tags |= TagMask::single(tag - 1);
+        }
+
+        Ok(())
+    }
+}
+
+fn config_path() -> Option<PathBuf> {
+    let config_home = std::env::var_os("XDG_CONFIG_HOME")
+        .filter(|path| !path.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")))?;
+
+    Some(config_home.join("flow").join("config.toml"))
+}
@@ -0,0 +1,232 @@
+use std::error::Error;
+use std::ffi::OsString;
+use std::io::{BufRead, BufReader, ErrorKind, Read, Write};
+use std::os::unix::ffi::{OsStrExt, OsStringExt};
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::PathBuf;
+use std::sync::mpsc;
+use std::thread;
+use std::time::Duration;
+
+use wayland_client::{Connection, EventQueue};
+
+use crate::client::Flow;
+use crate::commands::{self, Plan, Reply};
+use crate::config::Config;
+use crate::options::{Arguments, parse_from};
+
+impl Reply {
+    /// The wire format is the exit code and the length of stdout on a line each, followed by stdout and then stderr
+    fn write_to(&self, stream: &mut impl Write) -> std::io::Result<()> {
+        write!(
+            stream,
+            "{}\n{}\n{}{}",
+            self.code,
+            self.stdout.len(),
+            self.stdout,
+            self.stderr
+        )
+    }
+
+    fn read_from(stream: impl Read) -> Result<Self, Box<dyn Error>> {
+        let mut reader = BufReader::new(stream);
+        let mut line = String::new();
+
+        reader.read_line(&mut line)?;
+        let code = line.trim_end().parse()?;
+
+        line.clear();
+        reader.read_line(&mut line)?;
+        let mut stdout = vec![0; line.trim_end().parse()?];
+        reader.read_exact(&mut stdout)?;
+
+        let mut stderr = String::new();
+        reader.read_to_string(&mut stderr)?;
+
+        Ok(Self {
+            code,
+            stdout: String::from_utf8(stdout)?,
+            stderr,
+        })
+    }
+}
+
+/// The socket lives in $XDG_RUNTIME_DIR and is named after the Wayland display, so each river session gets its own daemon
+pub fn socket_path() -> Option<PathBuf> {
+    let runtime_dir = std::env::var_os("XDG_RUNTIME_DIR")?;
+    let display = std::env::var("WAYLAND_DISPLAY").unwrap_or_else(|_| String::from("wayland-0"));
+
+    Some(PathBuf::from(runtime_dir).join(format!("flow-{}.sock", display)))
+}
+
+/// Forward the command line arguments to a running daemon.
+/// Returns None if there is no daemon to talk to, in which case the command should be run directly.
+pub fn forward(args: &[OsString]) -> Option<Reply> {
+    let mut stream = UnixStream::connect(socket_path()?).ok()?;
+
+    // Arguments can't contain a nul byte, so use it to separate them
+    let request = args.join(OsString::from("\0").as_os_str());
+
+    let reply = stream
+        .write_all(request.as_bytes())
+        .and_then(|_| stream.shutdown(std::net::Shutdown::Write))
+        .map_err(Box::<dyn Error>::from)
+        .and_then(|_| Reply::read_from(&stream));
+
+    match reply {
+        Ok(reply) => Some(reply),
+        Err(error) => Some(Reply::from(Err(format!(
+            "Lost connection to the daemon: {}",
+            error
+        )
+        .into()))),
+    }
+}
+
+/// Keep the connection to river open and handle commands sent by clients until the connection is lost
+pub fn serve(
+    conn: &Connection,
+    event_queue: &mut EventQueue<Flow>,
+    flow: &mut Flow,
+) -> Result<(), Box<dyn Error>> {
+    let path = socket_path().ok_or("XDG_RUNTIME_DIR is not set")?;
+
+    // A socket that can't be connected to is left over from a daemon that didn't exit cleanly
+    if UnixStream::connect(&path).is_ok() {
+        return Err(format!("A daemon is already listening on {}", path.display()).into());
+    }
+    match std::fs::remove_file(&path) {
+        Err(error) if error.kind() != ErrorKind::NotFound => return Err(error.into()),
+        _ => (),
+    }
+
+    let listener = UnixListener::bind(&path)?;
+    let (sender, receiver) = mpsc::channel();
+
+    // Accept clients on a separate thread. After handing over a client, a sync request is sent on
+    // our queue so that the blocking dispatch below wakes up when the compositor answers it.
+    let conn = conn.clone();
+    let queue_handle = event_queue.handle();
+    thread::spawn(move || {
+        for stream in listener.incoming().flatten() {
+            if sender.send(stream).is_err() {
+                break;
+            }
+            conn.display().sync(&queue_handle, ());
+            // The main thread is blocked on reading, so nothing else is going to flush the request
+            if conn.flush().is_err() {
+                break;
+            }
+        }
+    });
+
+    loop {
+        event_queue.blocking_dispatch(flow)?;
+
+        // A roundtrip can dispatch the wakeup of a client that was just accepted,
+        // so only block again once nothing has been dispatched since looking for clients
+        loop {
+            flow.bind_status(&event_queue.handle());
+
+            let mut dispatched = false;
+            while let Ok(stream) = receiver.try_recv() {
+                handle_client(stream, event_queue, flow)?;
+                dispatched = true;
+            }
+            dispatched |= restore_layouts(event_queue, flow)?;
+
+            if !dispatched {
+                break;
+            }
+        }
+    }
+}
+
+/// Send the layouts remembered for newly focused tags. Returns whether anything was sent.
+fn restore_layouts(
+    event_queue: &mut EventQueue<Flow>,
+    flow: &mut Flow,
+) -> Result<bool, Box<dyn Error>> {
+    if flow
+        .outputs
+        .iter()
+        .all(|output| output.pending_layout.is_none())
+    {
+        return Ok(false);
+    }
+
+    // Layouts are remembered by the name the generator shows, output-layout takes its namespace
+    let config = Config::load().unwrap_or_else(|error| {
+        eprintln!("Error: {}", error);
+        Config::default()
+    });
+
+    let plans: Vec<Plan> = flow
+        .outputs
+        .iter_mut()
+        .filter_map(|output| {
+            let layout = output.pending_layout.take()?;
+            Some(Plan {
+                output: Some(output.name.to_owned()),
+                commands: vec![vec![
+                    String::from("output-layout"),
+                    config.layout_namespace(&layout).to_owned(),
+                ]],
+            })
+        })
+        .collect();
+
+    // Focus is given back to the output of the first seat, like for commands without --seat
+    flow.select_seat(None)?;
+    for plan in plans {
+        commands::send(flow, &event_queue.handle(), plan);
+    }
+    event_queue.roundtrip(flow)?;
+
+    // There's no client to tell, so failures go to the daemon's stderr
+    for result in flow.command_results.drain(..) {
+        if let Some(Err(failure_message)) = result {
+            eprintln!("Error: Failed to restore the layout: {}", failure_message);
+        }
+    }
+
+    Ok(true)
+}
+
+/// Run a single forwarded command and send the reply back.
+/// Only errors from the Wayland connection are returned, a misbehaving client is just dropped.
+fn handle_client(
+    mut stream: UnixStream,
+    event_queue: &mut EventQueue<Flow>,
+    flow: &mut Flow,
+) -> Result<(), Box<dyn Error>> {
+    // Don't let a client that never finishes its request hold up the daemon
+    let _ = stream.set_read_timeout(Some(Duration::from_secs(1)));
+
+    let mut request = Vec::new();
+    if stream.read_to_end(&mut request).is_err() {
+        return Ok(());
+    }
+
+    let args = request
+        .split(|&byte| byte == 0)
+        .map(|arg| OsString::from_vec(arg.to_vec()))
+        .collect();
+
+    // Make sure the state is current before acting on it
+    event_queue.roundtrip(flow)?;
+
+    // The config is read for every command, so changes apply without restarting the daemon
+    let reply = match Config::load().and_then(|config| Ok((parse_from(args, &config)?, config))) {
+        Ok((options, config)) => match options.command {
+            Arguments::Daemon { .. } => Reply::from(Err("A daemon is already running".into())),
+            Arguments::Watch { .. } => Reply::from(Err("watch can't be run by the daemon".into())),
+            _ => commands::execute(flow, event_queue, &config, options),
+        },
+        Err(error) => Reply::from(Err(error)),
+    };
+
+    let _ = reply.write_to(&mut stream);
+
+    Ok(())
+}
@@ -0,0 +1,374 @@
+use std::str::FromStr;
+
+use crate::client::Flow;
+use crate::config::Config;
+use crate::output::Output;
+use crate::watch::json_string;
+
+/// How watch and query print the state, for status bars that expect something other than flow's own JSON
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Format {
+    Template(Template),
+    /// A JSON object per output for a custom waybar module with `"return-type": "json"`
+    Waybar,
+    /// A status line with a block per tag, following the i3bar protocol
+    I3bar,
+}
+
+impl FromStr for Format {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "waybar" => Ok(Self::Waybar),
+            "i3bar" => Ok(Self::I3bar),
+            // Anything without a placeholder is most likely a misspelled preset
+            template if !template.contains('{') => Err(format!(
+                "Unknown format: {}, expected waybar, i3bar or a template",
+                template
+            )),
+            template => Template::parse(template).map(Self::Template),
+        }
+    }
+}
+
+impl Format {
+    /// The lines to print, each paired with the name of the output it shows.
+    /// i3bar shows every output on a single line, which has an empty name.
+    pub fn render(
+        &self,
+        flow: &Flow,
+        config: &Config,
+        outputs: &[&Output],
+    ) -> Vec<(String, String)> {
+        let per_output = |render: &dyn Fn(&Output) -> String| {
+            outputs
+                .iter()
+                .map(|output| (output.name.to_owned(), render(output)))
+                .collect()
+        };
+
+        match self {
+            Self::Template(template) => per_output(&|output| template.render(flow, config, output)),
+            Self::Waybar => per_output(&|output| waybar(flow, config, output)),
+            Self::I3bar => vec![(String::new(), i3bar(config, outputs))],
+        }
+    }
+}
+
+/// A line of text with placeholders in braces, rendered for each output.
+///
+/// - `{output}`, `{layout}`, `{mode}` and `{view}` are replaced by the state of the output and the seat
+/// - `{#tags}...{/tags}` is repeated for every tag within the number of tags, and inside it
+///   `{index}` is the tag number, `{name}` the tag name and `{state}` the first of focused, urgent, occupied or empty that applies
+/// - `{?focused}...{/focused}` is only kept if the condition holds, and `{!focused}...{/focused}` only if it doesn't.
+///   Inside `{#tags}` the conditions are focused, occupied, urgent and empty, and apply to the tag.
+///   Outside of it they are focused and urgent, and apply to the output.
+/// - `{{` and `}}` are a literal brace
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Template(Vec<Node>);
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+enum Node {
+    Text(String),
+    Value(Value),
+    Tags(Vec<Node>),
+    Condition {
+        condition: Condition,
+        negated: bool,
+        nodes: Vec<Node>,
+    },
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Value {
+    Output,
+    Layout,
+    Mode,
+    View,
+    Index,
+    Name,
+    State,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Condition {
+    Focused,
+    Occupied,
+    Urgent,
+    Empty,
+}
+
+impl Template {
+    pub fn parse(s: &str) -> Result<Self, String> {
+        let mut rest = s;
+        let nodes = parse_nodes(&mut rest, None)?;
+
+        Ok(Self(nodes))
+    }
+
+    pub fn render(&self, flow: &Flow, config: &Config, output: &Output) -> String {
+        let mut line = String::new();
+        render_nodes(&self.0, flow, config, output, None, &mut line);
+
+        line
+    }
+}
+
+/// Parse until the closing placeholder of the section, or the end if there is none.
+/// Tag values and conditions are only allowed inside a tags section.
+fn parse_nodes(rest: &mut &str, section: Option<(&str, bool)>) -> Result<Vec<Node>, String> {
+    let in_tags = section.is_some_and(|(_, in_tags)| in_tags);
+    let mut nodes = vec![];
+    let mut text = String::new();
+
+    loop {
+        let Some(position) = rest.find(['{', '}']) else {
+            text.push_str(rest);
+            *rest = "";
+            break;
+        };
+        text.push_str(&rest[..position]);
+        *rest = &rest[position..];
+
+        if let Some(after) = rest.strip_prefix("{{").or_else(|| rest.strip_prefix("}}")) {
+            text.push_str(&rest[..1]);
+            *rest = after;
+            continue;
+        }
+        if rest.starts_with('}') {
+            return Err(String::from(
+                "Unmatched } in the template, use }} for a literal brace",
+            ));
+        }
+
+        let end = rest
+            .find('}')
+            .ok_or_else(|| format!("Unclosed placeholder in the template: {}", rest))?;
+        let placeholder = &rest[1..end];
+        *rest = &rest[end + 1..];
+
+        if !text.is_empty() {
+            nodes.push(Node::Text(std::mem::take(&mut text)));
+        }
+
+        if let Some(name) = placeholder.strip_prefix('/') {
+            return match section {
+                Some((open, _)) if open == name => Ok(nodes),
+                _ => Err(format!("Unexpected {{/{}}} in the template", name)),
+            };
+        }
+
+        nodes.push(if placeholder == "#tags" {
+            if in_tags {
+                return Err(String::from("{#tags} can't be used inside {#tags}"));
+            }
+            Node::Tags(parse_nodes(rest, Some(("tags", true)))?)
+        } else if let Some(name) = placeholder.strip_prefix(['?', '!']) {
+            let condition = match name {
+                "focused" => Condition::Focused,
+                "urgent" => Condition::Urgent,
+                "occupied" if in_tags => Condition::Occupied,
+                "empty" if in_tags => Condition::Empty,
+                _ => return Err(format!("Unknown condition in the template: {}", name)),
+            };
+            Node::Condition {
+                condition,
+                negated: placeholder.starts_with('!'),
+                nodes: parse_nodes(rest, Some((name, in_tags)))?,
+            }
+        } else {
+            Node::Value(match placeholder {
+                "output" => Value::Output,
+                "layout" => Value::Layout,
+                "mode" => Value::Mode,
+                "view" => Value::View,
+                "index" if in_tags => Value::Index,
+                "name" if in_tags => Value::Name,
+                "state" if in_tags => Value::State,
+                _ => {
+                    return Err(format!(
+                        "Unknown placeholder in the template: {}",
+                        placeholder
+                    ));
+                }
+            })
+        });
+    }
+
+    if !text.is_empty() {
+        nodes.push(Node::Text(text));
+    }
+
+    match section {
+        Some((name, _)) => Err(format!("Missing {{/{}}} in the template", name)),
+        None => Ok(nodes),
+    }
+}
+
+fn render_nodes(
+    nodes: &[Node],
+    flow: &Flow,
+    config: &Config,
+    output: &Output,
+    tag: Option<u8>,
+    line: &mut String,
+) {
+    let seat = flow.seat();
+
+    for node in nodes {
+        match node {
+            Node::Text(text) => line.push_str(text),
+            Node::Value(value) => line.push_str(&match value {
+                Value::Output => output.name.to_owned(),
+                Value::Layout => output.layout_name.clone().unwrap_or_default(),
+                Value::Mode => seat.and_then(|seat| seat.mode.clone()).unwrap_or_default(),
+                Value::View => seat
+                    .and_then(|seat| seat.focused_view.clone())
+                    .unwrap_or_default(),
+                Value::Index => tag.map_or(0, |index| index + 1).to_string(),
+                Value::Name => tag.map(|index| config.tag_name(index)).unwrap_or_default(),
+                Value::State => tag
+                    .map(|index| TagState::of(output, index).name())
+                    .unwrap_or_default()
+                    .to_owned(),
+            }),
+            Node::Tags(nodes) => {
+                for index in 0..=config.tag_count(&output.name).last() {
+                    render_nodes(nodes, flow, config, output, Some(index), line);
+                }
+            }
+            Node::Condition {
+                condition,
+                negated,
+                nodes,
+            } => {
+                let holds = match tag {
+                    Some(index) => TagState::of(output, index).is(*condition),
+                    None => match condition {
+                        Condition::Focused => flow.is_focused(output),
+                        Condition::Urgent => {
+                            output.urgent_tags.is_some_and(|tags| !tags.is_empty())
+                        }
+                        Condition::Occupied | Condition::Empty => false,
+                    },
+                };
+
+                if holds != *negated {
+                    render_nodes(nodes, flow, config, output, tag, line);
+                }
+            }
+        }
+    }
+}
+
+/// What a single tag of an output holds
+struct TagState {
+    focused: bool,
+    occupied: bool,
+    urgent: bool,
+}
+
+impl TagState {
+    fn of(output: &Output, index: u8) -> Self {
+        Self {
+            focused: output.focused_tags.unwrap_or_default().contains(index),
+            occupied: output.occupied_mask().contains(index),
+            urgent: output.urgent_tags.unwrap_or_default().contains(index),
+        }
+    }
+
+    fn is(&self, condition: Condition) -> bool {
+        match condition {
+            Condition::Focused => self.focused,
+            Condition::Occupied => self.occupied,
+            Condition::Urgent => self.urgent,
+            Condition::Empty => !self.occupied,
+        }
+    }
+
+    /// Status bars only show tags that are in use
+    fn is_shown(&self) -> bool {
+        self.focused || self.occupied || self.urgent
+    }
+
+    fn name(&self) -> &'static str {
+        if self.focused {
+            "focused"
+        } else if self.urgent {
+            "urgent"
+        } else if self.occupied {
+            "occupied"
+        } else {
+            "empty"
+        }
+    }
+}
+
+/// The tag name as Pango markup, in bold if focused and underlined if urgent
+fn tag_markup(config: &Config, output: &Output, index: u8) -> String {
+    let state = TagState::of(output, index);
+    let mut markup = escape_markup(&config.tag_name(index));
+
+    if state.urgent {
+        markup = format!("<u>{}</u>", markup);
+    }
+    if state.focused {
+        markup = format!("<b>{}</b>", markup);
+    }
+
+    markup
+}
+
+/// The tags in use as Pango markup, with the output as tooltip and classes for a focused output and urgent tags
+fn waybar(flow: &Flow, config: &Config, output: &Output) -> String {
+    let tags: Vec<String> = (0..=config.tag_count(&output.name).last())
+        .filter(|&index| TagState::of(output, index).is_shown())
+        .map(|index| tag_markup(config, output, index))
+        .collect();
+
+    let mut classes = vec![];
+    if flow.is_focused(output) {
+        classes.push(json_string("focused"));
+    }
+    if output.urgent_tags.is_some_and(|tags| !tags.is_empty()) {
+        classes.push(json_string("urgent"));
+    }
+
+    format!(
+        r#"{{"text":{},"tooltip":{},"class":[{}]}}"#,
+        json_string(&tags.join(" ")),
+        json_string(&output.name),
+        classes.join(","),
+    )
+}
+
+/// A block for every tag in use on every output. The instance is the output and tag number, for click events.
+fn i3bar(config: &Config, outputs: &[&Output]) -> String {
+    let blocks: Vec<String> = outputs
+        .iter()
+        .flat_map(|output| {
+            (0..=config.tag_count(&output.name).last())
+                .filter(|&index| TagState::of(output, index).is_shown())
+                .map(|index| {
+                    format!(
+                        r#"{{"name":"flow","instance":{},"full_text":{},"markup":"pango","urgent":{}}}"#,
+                        json_string(&format!("{}:{}", output.name, index + 1)),
+                        json_string(&tag_markup(config, output, index)),
+                        TagState::of(output, index).urgent,
+                    )
+                })
+        })
+        .collect();
+
+    format!("[{}]", blocks.join(","))
+}
+
+/// Escape text for use in Pango markup
+fn escape_markup(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('\'', "&apos;")
+        .replace('"', "&quot;")
+}
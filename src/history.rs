@@ -0,0 +1,53 @@
+use crate::tags::{Direction, TagMask};
+
+/// The most tagmasks remembered per output
+const MAX_ENTRIES: usize = 100;
+
+/// The tags focused on an output over time, moved through like a browser history
+#[derive(Clone, Debug, Default)]
+pub struct TagHistory {
+    entries: Vec<TagMask>,
+    /// Index of the current entry
+    position: usize,
+}
+
+impl TagHistory {
+    /// Remember newly focused tags. Anything that could be gone forward to is dropped,
+    /// unless the tags are the ones that were just moved to.
+    pub fn record(&mut self, tags: TagMask) {
+        if self.entries.get(self.position) == Some(&tags) {
+            return;
+        }
+
+        self.entries.truncate(self.position + 1);
+        self.entries.push(tags);
+
+        if self.entries.len() > MAX_ENTRIES {
+            self.entries.remove(0);
+        }
+        self.position = self.entries.len() - 1;
+    }
+
+    /// Move to the previous or next entry and return its tags, if there is one
+    pub fn step(&mut self, direction: Direction) -> Option<TagMask> {
+        let position = self.neighbour(direction)?;
+
+        self.position = position;
+        Some(self.entries[position])
+    }
+
+    /// The tags of the previous or next entry, without moving to it
+    pub fn peek(&self, direction: Direction) -> Option<TagMask> {
+        self.neighbour(direction)
+            .map(|position| self.entries[position])
+    }
+
+    fn neighbour(&self, direction: Direction) -> Option<usize> {
+        let position = match direction {
+            Direction::Previous => self.position.checked_sub(1)?,
+            Direction::Next => self.position + 1,
+        };
+
+        (position < self.entries.len()).then_some(position)
+    }
+}
@@ -1,29 +1,43 @@
 use crate::client::Flow;
+use crate::config::Config;
 use crate::options::{Arguments, parse_args};
-use std::error::Error;
-use wayland_client::{Connection, Proxy};
+use wayland_client::{Connection, EventQueue};
 
+mod batch;
 mod client;
+mod commands;
+mod config;
+mod daemon;
+mod format;
+mod history;
 mod options;
 mod output;
 mod protocols;
+mod query;
 mod seat;
+mod tags;
+mod watch;
 
 static ROUNDTRIP_EXPECT: &str = "All requests in queue must be sent and handled before proceeding.";
 
 fn main() {
+    // The config is needed to parse tag names
+    let config = match Config::load() {
+        Ok(config) => config,
+        Err(error) => {
+            eprintln!("Error: {}", error);
+            std::process::exit(1);
+        }
+    };
+
     // Parse the options
-    let command: Result<Arguments, Box<dyn Error>> = match parse_args() {
-        Ok(args) => match &args {
-            Arguments::Global { _help: _ } => {
+    let mut options = match parse_args(&config) {
+        Ok(options) => match &options.command {
+            Arguments::Global { .. } => {
                 print!("{}", options::HELP);
                 std::process::exit(0);
             }
-            // Should probably check here that the provided arguments to the command are correct before proceeding
-            Arguments::CycleTags { .. } => Ok(args),
-            Arguments::ToggleTags { .. } => Ok(args),
-            Arguments::FocusUrgentTags => Ok(args),
-            Arguments::FocusSetViewTags { .. } => Ok(args),
+            _ => options,
         },
         Err(error) => {
             eprintln!("Error: {}", error);
@@ -31,6 +45,89 @@ fn main() {
         }
     };
 
+    if let Arguments::Daemon { restore_layouts } = options.command {
+        let (conn, mut event_queue, mut flow) = connect();
+        flow.restore_layouts = restore_layouts;
+
+        if let Err(error) = daemon::serve(&conn, &mut event_queue, &mut flow) {
+            eprintln!("Error: {}", error);
+            std::process::exit(1);
+        }
+        return;
+    }
+
+    // Watching holds on to its own connection, there's nothing to forward
+    if let Arguments::Watch { format } = &options.command {
+        let (_conn, mut event_queue, mut flow) = connect();
+
+        if let Err(error) = flow.select_seat(options.seat.as_deref()) {
+            eprintln!("Error: {}", error);
+            std::process::exit(1);
+        }
+
+        if let Err(error) = watch::run(
+            &mut event_queue,
+            &mut flow,
+            &config,
+            options.output.as_deref(),
+            format.as_ref(),
+        ) {
+            eprintln!("Error: {}", error);
+            std::process::exit(1);
+        }
+        return;
+    }
+
+    let mut args: Vec<_> = std::env::args_os().skip(1).collect();
+    let flags: Vec<&str> = [
+        ("--dry-run", options.dry_run),
+        ("--explain", options.explain),
+    ]
+    .into_iter()
+    .filter_map(|(flag, set)| set.then_some(flag))
+    .collect();
+
+    // Commands of a batch are read here, so the daemon gets them along with the rest of the arguments
+    if let Arguments::Batch {
+        steps,
+        file,
+        stop_on_error,
+    } = &mut options.command
+        && steps.is_empty()
+    {
+        match batch::read_steps(file.as_deref()) {
+            Ok(read) => *steps = read,
+            Err(error) => {
+                eprintln!("Error: {}", error);
+                std::process::exit(1);
+            }
+        }
+        args = batch::to_args(
+            steps,
+            options.output.as_deref(),
+            options.seat.as_deref(),
+            &flags,
+            *stop_on_error,
+        );
+    }
+
+    // Let a running daemon handle the command, it already has the state at hand
+    let reply = match daemon::forward(&args) {
+        Some(reply) => reply,
+        None => {
+            let (_conn, mut event_queue, mut flow) = connect();
+            let reply = commands::execute(&mut flow, &mut event_queue, &config, options);
+            flow.destroy();
+            reply
+        }
+    };
+
+    reply.print();
+    std::process::exit(reply.code);
+}
+
+/// Connect to river, bind the globals and gather the initial state of the seat and outputs
+fn connect() -> (Connection, EventQueue<Flow>, Flow) {
     let conn = Connection::connect_to_env().expect("Failed to connect to the Wayland server!");
 
     let display = conn.display();
@@ -42,90 +139,21 @@ fn main() {
 
     let mut flow = Flow::new();
 
+    // The first roundtrip binds the globals, the second one receives the output names
+    event_queue.roundtrip(&mut flow).expect(ROUNDTRIP_EXPECT);
     event_queue.roundtrip(&mut flow).expect(ROUNDTRIP_EXPECT);
 
-    // Get the seat status
-    if let Some(seat) = flow.seat.as_mut() {
-        seat.seat_status = Some(
-            flow.status_manager
-                .as_ref()
-                .expect("A status manager should exist.")
-                .get_river_seat_status(&seat.wlseat, &queue_handle, ()),
-        );
-    } else {
+    if flow.status_manager.is_none() {
+        panic!("A status manager should exist.")
+    }
+    if flow.seats.is_empty() {
         panic!("Failed to get the seat status. A seat should exist but was None.")
     }
 
-    event_queue.roundtrip(&mut flow).expect(ROUNDTRIP_EXPECT);
-
-    // Setup the outputs, pass on the wloutput id so we bind the state correctly to each output
-    for output in &mut flow.outputs {
-        if let Some(status_manager) = flow.status_manager.as_ref() {
-            output.status = Some(status_manager.get_river_output_status(
-                &output.wloutput,
-                &queue_handle,
-                output.wloutput.id(),
-            ));
-        }
-    }
+    // Get the seat status and the status of each output
+    flow.bind_status(&queue_handle);
 
     event_queue.roundtrip(&mut flow).expect(ROUNDTRIP_EXPECT);
 
-    match command {
-        Ok(Arguments::CycleTags {
-            direction,
-            n_tags,
-            skip_unoccupied,
-        }) => {
-            // Find the focused output
-            if let Some(output) = flow.find_output("focused") {
-                // If there are no n_tags assigned, or if unwrap fails, we assume default of 9
-                let new_tags = output.cycle_tags(&direction, &n_tags.unwrap_or(9), skip_unoccupied);
-
-                flow.send_command(
-                    vec![String::from("set-focused-tags"), new_tags.to_string()],
-                    &queue_handle,
-                );
-            }
-        }
-        Ok(Arguments::ToggleTags { to_tags }) => {
-            if let Some(output) = flow.find_output("focused") {
-                if output.toggle_tags(&to_tags) {
-                    flow.send_command(vec![String::from("focus-previous-tags")], &queue_handle);
-                } else {
-                    flow.send_command(
-                        vec![String::from("set-focused-tags"), to_tags.to_string()],
-                        &queue_handle,
-                    );
-                }
-            }
-        }
-        Ok(Arguments::FocusUrgentTags) => {
-            // Find any urgent output
-            if let Some(output) = flow.find_output("urgent")
-                && let Some(urgent_tags) = output.urgent_tags {
-                    flow.send_command(
-                        vec![String::from("focus-output"), output.name.to_owned()],
-                        &queue_handle,
-                    );
-                    flow.send_command(
-                        vec![String::from("set-focused-tags"), urgent_tags.to_string()],
-                        &queue_handle,
-                    )
-                }
-        }
-        Ok(Arguments::FocusSetViewTags { to_tags }) => {
-            flow.send_command(
-                vec![String::from("set-view-tags"), to_tags.to_string()],
-                &queue_handle,
-            );
-            flow.send_command(
-                vec![String::from("set-focused-tags"), to_tags.to_string()],
-                &queue_handle,
-            );
-        }
-        _ => (),
-    }
-    event_queue.roundtrip(&mut flow).expect(ROUNDTRIP_EXPECT);
-    flow.destroy();
+    (conn, event_queue, flow)
 }
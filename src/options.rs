@@ -1,3 +1,12 @@
+use std::ffi::OsString;
+use std::path::PathBuf;
+use std::str::FromStr;
+
+use crate::batch;
+use crate::config::Config;
+use crate::format::Format;
+use crate::tags::{Direction, TagCount, TagExpression, TagFilter};
+
 pub const HELP: &str = r#"flow
 ====
 
@@ -5,51 +14,432 @@ USAGE:
   flow [COMMAND] [ARGS]
 FLAGS:
   -h, --help            Prints help information
+      --output NAME     Act on the output with this name instead of the focused output.
+                        Focus is given back to the focused output afterwards.
+      --seat NAME       Use the focused output of this seat and run commands on it.
+                        Defaults to the first seat.
+      --dry-run         Print the river commands instead of running them.
+      --explain         Print the state the river commands were worked out from, along with the commands.
   -o, --occupied        This flag can be appended to the cycle-tags command to only cycle through occupied tags.
+      --all             Cycle through all tags, even if the config says to only cycle through occupied tags.
 COMMAND:
-  cycle-tags            Takes two arguments. Direction (next or previous) and an optional number of available tags (Default: 9).
+  cycle-tags            Takes two arguments. Direction (next or previous) and an optional number of available tags (1-32, Default: 9).
+                        Use --step N to move N tags at once, and --no-wrap to fail instead of wrapping around at the first or last tag.
+  cycle-view-tags       Move the focused view to the next or previous tag. Takes a direction, an optional number of tags,
+                        --step and --no-wrap like cycle-tags. Use -o/--occupied or --empty to skip tags, and --follow
+                        to focus the tags as well.
   toggle-tags           Toggle previous tags if selected tags already focused.
-  focus-urgent-tags     Focus urgent tags on an output.
+  focus-empty-tag       Focus the first tag without views. Use --send-view to take the focused view along
+                        and --after-current to look after the focused tags first.
+  focus-occupied        Focus the Nth occupied tag, counting from 1.
+  send-occupied         Send the focused view to the Nth occupied tag, counting from 1.
+  swap-output-tags      Swap the focused tags of the focused output and another output, given with --with NAME.
+                        Without --with, the only other output is used.
+  focus-urgent-tags     Focus urgent tags on an output. Repeat to move through every urgent output, oldest urgency first.
+                        With --return, focus the tags that were focused before the first jump. Needs a running daemon.
   focus-set-view-tags   Set view tags and then focus the tags.
+  cycle-layout          Takes a direction (next or previous) and the names of layout generators to cycle through.
+                        The layout in use is found through layout-namespaces in the config, e.g. "[]=" = "rivertile".
+  scratchpad            Takes an action (toggle, send or init) and an optional scratchpad name from the config.
+                        toggle shows or hides the scratchpad if it holds any views, send moves the focused view to it
+                        and init keeps new views off every scratchpad.
+  back                  Focus the tags that were focused before on the output. Needs a running daemon.
+  forward               Focus the tags that were focused before going back.
+  batch                 Run one command per line from stdin, or from a file given with --file, over one connection.
+                        Arguments with spaces can be quoted like in a shell.
+                        Commands can also be chained on the command line with a ; in between, e.g. flow toggle-tags 1 \; focus-urgent-tags
+                        Options before the first command apply to every command, and a ; after -- is passed on to river.
+                        With --stop-on-error, the remaining commands are skipped once one fails.
+  run                   Send the river command after -- as is, like riverctl does, and print what river returns.
+                        With --output, the command is run with that output focused.
+  query                 Print the state of every output and the seat as a table, or as JSON with --json.
+                        With --output, only that output is included.
+  watch                 Print the state of each output as a line of JSON whenever it changes.
+                        query and watch take --format with waybar, i3bar or a template, see FORMAT below.
+  daemon                Keep a connection to river open and serve commands over a socket in $XDG_RUNTIME_DIR.
+                        Commands are forwarded to a running daemon and run directly otherwise.
+                        With --restore-layouts, the layout last used on a set of tags is restored when it's focused again.
+CONFIG:
+  Read from $XDG_CONFIG_HOME/flow/config.toml. Options given on the command line take precedence.
+TAGS:
+  Commands that take tags accept a comma separated list of tag numbers (3), ranges (4-6), the focused, occupied
+  or urgent tags of the focused output (focused+1), a raw tagmask (mask:64) or tag names from the config.
+  Prefix with ~ to invert.
+FORMAT:
+  A template prints a line per output with {output}, {layout}, {mode} and {view} replaced. {#tags}...{/tags} is
+  repeated for every tag, with {index}, {name} and {state} (focused, urgent, occupied or empty) of the tag.
+  {?cond}...{/cond} is only kept if cond holds and {!cond}...{/cond} if it doesn't, where cond is focused, occupied,
+  urgent or empty for a tag and focused or urgent for the output. Use {{ and }} for literal braces, e.g.
+  flow watch --format '{#tags}{!empty}{?focused}[{/focused}{name}{?focused}]{/focused} {/empty}{/tags}'
 "#;
 
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ScratchpadAction {
+    /// Show or hide the scratchpad on the output
+    Toggle,
+    /// Move the focused view to the scratchpad
+    Send,
+    /// Keep new views off every scratchpad
+    Init,
+}
+
+impl FromStr for ScratchpadAction {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "toggle" => Ok(Self::Toggle),
+            "send" => Ok(Self::Send),
+            "init" => Ok(Self::Init),
+            _ => Err(format!(
+                "Unknown scratchpad action: {}, expected toggle, send or init",
+                s
+            )),
+        }
+    }
+}
+
 pub enum Arguments {
     Global {
         _help: bool,
     },
     CycleTags {
-        direction: String,
-        n_tags: Option<u8>,
-        skip_unoccupied: bool,
+        direction: Direction,
+        n_tags: Option<TagCount>,
+        skip_unoccupied: Option<bool>,
+        /// How many tags to move at once
+        steps: u8,
+        /// Go on from the other end when moving past the first or last tag
+        wrap: bool,
+    },
+    CycleViewTags {
+        direction: Direction,
+        n_tags: Option<TagCount>,
+        filter: TagFilter,
+        /// Focus the tags the view was moved to
+        follow: bool,
+        steps: u8,
+        wrap: bool,
     },
     ToggleTags {
-        to_tags: u32,
+        to_tags: TagExpression,
+    },
+    FocusEmptyTag {
+        /// Move the focused view to the tag first
+        send_view: bool,
+        /// Look for the first empty tag after the focused tags instead of from the first tag
+        after_current: bool,
+    },
+    /// Focus the Nth occupied tag, or send the focused view to it
+    OccupiedTag {
+        /// 1-based position among the occupied tags
+        position: usize,
+        send_view: bool,
+    },
+    SwapOutputTags {
+        /// The output to swap with, the only other output if None
+        with: Option<String>,
+    },
+    FocusUrgentTags {
+        /// Go back to where focus was before the jump instead
+        jump_back: bool,
     },
-    FocusUrgentTags,
     FocusSetViewTags {
-        to_tags: u32,
-    },
-}
-
-pub fn parse_args() -> Result<Arguments, Box<dyn std::error::Error>> {
-    let mut pargs = pico_args::Arguments::from_env();
-
-    match pargs.subcommand()?.as_deref() {
-        Some("cycle-tags") => Ok(Arguments::CycleTags {
-            direction: pargs.free_from_str()?,
-            n_tags: pargs.opt_free_from_str()?,
-            skip_unoccupied: pargs.contains(["-o", "--occupied"]),
-        }),
-        Some("toggle-tags") => Ok(Arguments::ToggleTags {
-            to_tags: pargs.free_from_str()?,
-        }),
-        Some("focus-urgent-tags") => Ok(Arguments::FocusUrgentTags),
-        Some("focus-set-view-tags") => Ok(Arguments::FocusSetViewTags {
-            to_tags: pargs.free_from_str()?,
-        }),
-        Some(_) => Err("Unknown subcommand".into()),
-        None => Ok(Arguments::Global {
+        to_tags: TagExpression,
+    },
+    CycleLayout {
+        direction: Direction,
+        layouts: Vec<String>,
+    },
+    Scratchpad {
+        action: ScratchpadAction,
+        name: Option<String>,
+    },
+    /// Move back or forward through the tags focused on an output
+    History {
+        direction: Direction,
+    },
+    /// Run several commands in order over one connection
+    Batch {
+        /// The arguments of each command, read from the file or stdin if empty
+        steps: Vec<Vec<OsString>>,
+        /// Read the commands from this file instead of stdin
+        file: Option<PathBuf>,
+        /// Skip the remaining commands once one fails
+        stop_on_error: bool,
+    },
+    /// Send any command to river
+    Run {
+        river_command: Vec<String>,
+    },
+    /// Print the state of the outputs and the seat
+    Query {
+        json: bool,
+        format: Option<Format>,
+    },
+    /// Print the state whenever it changes
+    Watch {
+        format: Option<Format>,
+    },
+    Daemon {
+        /// Switch back to the layout last used on a set of tags when it's focused again
+        restore_layouts: bool,
+    },
+}
+
+/// A command along with the options that apply to every command
+pub struct Options {
+    /// Act on this output instead of the focused one
+    pub output: Option<String>,
+    /// Use this seat instead of the first one
+    pub seat: Option<String>,
+    /// Print the river commands instead of running them
+    pub dry_run: bool,
+    /// Print the state the commands were worked out from, along with the commands
+    pub explain: bool,
+    pub command: Arguments,
+}
+
+pub fn parse_args(config: &Config) -> Result<Options, Box<dyn std::error::Error>> {
+    parse_from(std::env::args_os().skip(1).collect(), config)
+}
+
+/// Parse a list of arguments, e.g. the ones forwarded to the daemon by a client.
+/// The config is needed to look up tag names.
+pub fn parse_from(
+    args: Vec<OsString>,
+    config: &Config,
+) -> Result<Options, Box<dyn std::error::Error>> {
+    // Commands chained with ; are run as a batch. They are split before anything else is parsed,
+    // so options given to a command aren't taken for the whole batch. Options given before the first
+    // command are, along with batch and its options.
+    if batch::is_chained(&args) {
+        let (options, steps) = batch::split_steps(&args);
+        let mut pargs = pico_args::Arguments::from_vec(options);
+
+        let output = pargs.opt_value_from_str("--output")?;
+        let seat = pargs.opt_value_from_str("--seat")?;
+        let dry_run = pargs.contains("--dry-run");
+        let explain = pargs.contains("--explain");
+        let stop_on_error = pargs.contains("--stop-on-error");
+
+        if stop_on_error && pargs.subcommand()?.as_deref() != Some("batch") {
+            return Err("--stop-on-error is an option of batch".into());
+        }
+
+        return Ok(Options {
+            output,
+            seat,
+            dry_run,
+            explain,
+            command: Arguments::Batch {
+                steps,
+                file: None,
+                stop_on_error,
+            },
+        });
+    }
+
+    // Everything after -- is passed on to river as is, so none of it can be taken for an option
+    let (args, mut passthrough) = match args.iter().position(|arg| arg == "--") {
+        Some(index) => {
+            let mut args = args;
+            let passthrough = args.split_off(index + 1);
+            args.pop();
+            (args, Some(passthrough))
+        }
+        None => (args, None),
+    };
+
+    let mut pargs = pico_args::Arguments::from_vec(args);
+
+    // Global options can be given anywhere, take them out before looking for the subcommand
+    let output = pargs.opt_value_from_str("--output")?;
+    let seat = pargs.opt_value_from_str("--seat")?;
+    let dry_run = pargs.contains("--dry-run");
+    let explain = pargs.contains("--explain");
+
+    let command = match pargs.subcommand()?.as_deref() {
+        Some("cycle-tags") => {
+            // Flags have to be taken before the free arguments, otherwise they could be mistaken for one
+            let skip_unoccupied = match (
+                pargs.contains(["-o", "--occupied"]),
+                pargs.contains("--all"),
+            ) {
+                (true, true) => return Err("--occupied and --all can't be combined".into()),
+                (true, false) => Some(true),
+                (false, true) => Some(false),
+                (false, false) => None,
+            };
+            let steps = opt_steps(&mut pargs)?;
+            let wrap = !pargs.contains("--no-wrap");
+
+            Arguments::CycleTags {
+                direction: pargs.free_from_str()?,
+                n_tags: pargs.opt_free_from_str()?,
+                skip_unoccupied,
+                steps,
+                wrap,
+            }
+        }
+        Some("cycle-view-tags") => {
+            let filter = match (
+                pargs.contains(["-o", "--occupied"]),
+                pargs.contains("--empty"),
+            ) {
+                (true, true) => return Err("--occupied and --empty can't be combined".into()),
+                (true, false) => TagFilter::Occupied,
+                (false, true) => TagFilter::Empty,
+                (false, false) => TagFilter::All,
+            };
+            let follow = pargs.contains("--follow");
+            let steps = opt_steps(&mut pargs)?;
+            let wrap = !pargs.contains("--no-wrap");
+
+            Arguments::CycleViewTags {
+                direction: pargs.free_from_str()?,
+                n_tags: pargs.opt_free_from_str()?,
+                filter,
+                follow,
+                steps,
+                wrap,
+            }
+        }
+        Some("toggle-tags") => Arguments::ToggleTags {
+            to_tags: free_tags(&mut pargs, config)?,
+        },
+        Some("focus-empty-tag") => Arguments::FocusEmptyTag {
+            send_view: pargs.contains("--send-view"),
+            after_current: pargs.contains("--after-current"),
+        },
+        Some(command @ ("focus-occupied" | "send-occupied")) => Arguments::OccupiedTag {
+            position: free_position(&mut pargs)?,
+            send_view: command == "send-occupied",
+        },
+        Some("swap-output-tags") => Arguments::SwapOutputTags {
+            with: pargs.opt_value_from_str("--with")?,
+        },
+        Some("focus-urgent-tags") => Arguments::FocusUrgentTags {
+            jump_back: pargs.contains("--return"),
+        },
+        Some("focus-set-view-tags") => Arguments::FocusSetViewTags {
+            to_tags: free_tags(&mut pargs, config)?,
+        },
+        Some("cycle-layout") => {
+            let direction = pargs.free_from_str()?;
+            let layouts = pargs
+                .finish()
+                .into_iter()
+                .map(|layout| {
+                    layout
+                        .into_string()
+                        .map_err(|layout| format!("Invalid layout name: {:?}", layout))
+                })
+                .collect::<Result<Vec<String>, String>>()?;
+
+            if layouts.is_empty() {
+                return Err("cycle-layout needs at least one layout to cycle through".into());
+            }
+
+            Arguments::CycleLayout { direction, layouts }
+        }
+        Some("scratchpad") => Arguments::Scratchpad {
+            action: pargs.free_from_str()?,
+            name: pargs.opt_free_from_str()?,
+        },
+        Some("back") => Arguments::History {
+            direction: Direction::Previous,
+        },
+        Some("forward") => Arguments::History {
+            direction: Direction::Next,
+        },
+        Some("batch") => {
+            let stop_on_error = pargs.contains("--stop-on-error");
+            let file = pargs.opt_value_from_str("--file")?;
+            let step = pargs.finish();
+
+            Arguments::Batch {
+                steps: if step.is_empty() { vec![] } else { vec![step] },
+                file,
+                stop_on_error,
+            }
+        }
+        Some("run") => {
+            let river_command = passthrough
+                .take()
+                .unwrap_or_else(|| pargs.finish())
+                .into_iter()
+                .map(|arg| {
+                    arg.into_string()
+                        .map_err(|arg| format!("Invalid argument: {:?}", arg))
+                })
+                .collect::<Result<Vec<String>, String>>()?;
+
+            if river_command.is_empty() {
+                return Err("run needs a river command, e.g. flow run -- focus-view next".into());
+            }
+
+            Arguments::Run { river_command }
+        }
+        Some("query") => {
+            let json = pargs.contains("--json");
+            let format = pargs.opt_value_from_str("--format")?;
+
+            if json && format.is_some() {
+                return Err("--json and --format can't be used together".into());
+            }
+
+            Arguments::Query { json, format }
+        }
+        Some("watch") => Arguments::Watch {
+            format: pargs.opt_value_from_str("--format")?,
+        },
+        Some("daemon") => Arguments::Daemon {
+            restore_layouts: pargs.contains("--restore-layouts"),
+        },
+        Some(_) => return Err("Unknown subcommand".into()),
+        None => Arguments::Global {
             _help: pargs.contains(["-h", "--help"]),
-        }),
+        },
+    };
+
+    if passthrough.is_some() {
+        return Err("Only run takes arguments after --".into());
+    }
+
+    Ok(Options {
+        output,
+        seat,
+        dry_run,
+        explain,
+        command,
+    })
+}
+
+/// Take the next free argument as a 1-based position
+fn free_position(pargs: &mut pico_args::Arguments) -> Result<usize, Box<dyn std::error::Error>> {
+    let position: String = pargs.free_from_str()?;
+
+    match position.parse::<usize>() {
+        Ok(position) if position >= 1 => Ok(position),
+        _ => Err(format!("Invalid position: {}, expected a number from 1", position).into()),
+    }
+}
+
+/// Take the number of tags to move at once from --step, 1 if it isn't given
+fn opt_steps(pargs: &mut pico_args::Arguments) -> Result<u8, Box<dyn std::error::Error>> {
+    match pargs.opt_value_from_str("--step")? {
+        Some(0) => Err("--step must be at least 1".into()),
+        steps => Ok(steps.unwrap_or(1)),
     }
 }
+
+/// Take the next free argument as tags
+fn free_tags(
+    pargs: &mut pico_args::Arguments,
+    config: &Config,
+) -> Result<TagExpression, Box<dyn std::error::Error>> {
+    let tags: String = pargs.free_from_str()?;
+
+    Ok(TagExpression::parse(&tags, &config.tag_names)?)
+}
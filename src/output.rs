@@ -1,166 +1,215 @@
+use std::collections::HashMap;
+use std::time::Instant;
+
 use wayland_client::protocol::wl_output::WlOutput;
 
+use crate::config::Config;
+use crate::history::TagHistory;
 use crate::protocols::river_protocols::zriver_output_status_v1::ZriverOutputStatusV1;
+use crate::tags::{Direction, TagCount, TagFilter, TagMask};
 
-#[derive(Debug)]
+#[derive(Clone, Debug)]
 pub struct Output {
     pub name: String,
+    pub global_name: u32,
     pub wloutput: WlOutput,
     pub status: Option<ZriverOutputStatusV1>,
-    pub focused: bool,
-    pub urgent_tags: Option<u32>,
-    pub focused_tags: Option<u32>,
+    pub urgent_tags: Option<TagMask>,
+    /// When the output went from no urgent tags to some, to handle the oldest urgency first
+    pub urgent_since: Option<Instant>,
+    pub focused_tags: Option<TagMask>,
     pub occupied_tags: Vec<u8>,
+    /// Name of the layout generator in use, None if there is none
+    pub layout_name: Option<String>,
+    /// The layout last used on each set of focused tags
+    pub layouts: HashMap<TagMask, String>,
+    /// A remembered layout to switch to, since its tags were just focused
+    pub pending_layout: Option<String>,
+    /// Every tagmask focused on the output since flow connected
+    pub history: TagHistory,
 }
 
 impl Output {
     /// Set up state for an output
-    pub fn new(name: String, wloutput: WlOutput) -> Self {
+    pub fn new(name: String, global_name: u32, wloutput: WlOutput) -> Self {
         Self {
             name,
+            global_name,
             wloutput,
             status: None,
-            focused: false,
             urgent_tags: None,
+            urgent_since: None,
             focused_tags: None,
             occupied_tags: vec![],
+            layout_name: None,
+            layouts: HashMap::new(),
+            pending_layout: None,
+            history: TagHistory::default(),
         }
     }
-    /// Cycle the tagmask in either next or previous direction
-    pub fn cycle_tags(&self, direction: &str, n_tags: &u8, skip_unoccupied: bool) -> u32 {
-        let tags: u32 = self.focused_tags.unwrap_or_default();
-        let mut new_tags: u32 = tags;
-
-        let occupied_tags = self.find_set_bits_positions(*n_tags);
-
-        match direction {
-            // Only skip unoccupied on user flag and if there are more than one occupied tag
-            "next" | "previous" if skip_unoccupied && occupied_tags.len() > 1 => {
-                let mut old_bits: Vec<u8> = Vec::new();
-                let mut new_bits: Vec<u8> = Vec::new();
-
-                let wrap_around = if direction == "next" { 0 } else { n_tags - 1 };
-
-                for tag_index in (0..*n_tags).filter(|&i| (tags >> i) & 1 == 1) {
-                    // Find the next occupied position
-                    let mut next_occupied = self
-                        .find_next_occupied(&occupied_tags, direction, tag_index)
-                        .unwrap_or(wrap_around);
-
-                    // Handle the cases where we hit the wrap_around and need to go again to find the next occupied
-                    if next_occupied == wrap_around && !occupied_tags.contains(&wrap_around) {
-                        next_occupied = self
-                            .find_next_occupied(&occupied_tags, direction, next_occupied)
-                            .unwrap_or(wrap_around);
-                    }
-
-                    // Set the next occupied tag
-                    new_tags |= 1 << next_occupied;
-
-                    // Add each old and new bit to vector
-                    old_bits.push(tag_index);
-                    new_bits.push(next_occupied);
-                }
-
-                // Go over the old bits and unset those that are no longer part of the tagmask
-                for bit in old_bits {
-                    if !new_bits.contains(&bit) {
-                        new_tags &= !(1 << bit);
-                    }
-                }
-            }
-
-            "next" => {
-                let mut tags = tags;
-                new_tags = 0;
-                let last_tag: u32 = 1 << (n_tags - 1);
-
-                if tags & last_tag != 0 {
-                    tags ^= last_tag;
-                    new_tags = 1;
-                }
-
-                new_tags |= tags << 1;
-            }
-            "previous" => {
-                let mut tags = tags;
-                new_tags = 0;
-                let last_tag: u32 = 1 << (n_tags - 1);
-
-                if (tags & 1) != 0 {
-                    tags ^= 1;
-                    new_tags = last_tag;
-                }
-                new_tags |= tags >> 1;
-            }
-            _ => (),
+
+    /// Take tags as focused, either reported by river or focused by a command river hasn't reported on yet.
+    /// With restore_layouts, the layout last used on the tags is queued if it isn't in use.
+    pub fn focus_tags(&mut self, tags: TagMask, restore_layouts: bool) {
+        let changed = self.focused_tags != Some(tags);
+        self.focused_tags = Some(tags);
+        self.history.record(tags);
+
+        // The layout name is only sent when it changes, so tags focused without a change use the current layout
+        if let Some(layout_name) = &self.layout_name {
+            self.layouts
+                .entry(tags)
+                .or_insert_with(|| layout_name.to_owned());
+        }
+
+        // Only restore when other tags are focused, not when the same tags are sent again
+        if restore_layouts && changed {
+            self.pending_layout = self
+                .layouts
+                .get(&tags)
+                .filter(|layout| Some(*layout) != self.layout_name.as_ref())
+                .cloned();
         }
+    }
 
-        new_tags
+    /// The layout namespace after the current one in a list of namespaces, wrapping around at the edges.
+    /// The current layout name is looked up in the config to find its namespace.
+    /// If the current layout isn't in the list, the first or last layout is used depending on the direction.
+    pub fn cycle_layout<'a>(
+        &self,
+        direction: Direction,
+        layouts: &'a [String],
+        config: &Config,
+    ) -> Option<&'a str> {
+        let namespace = self
+            .layout_name
+            .as_deref()
+            .map(|name| config.layout_namespace(name));
+        let current = layouts
+            .iter()
+            .position(|layout| Some(layout.as_str()) == namespace);
+
+        let index = match (direction, current) {
+            (Direction::Next, Some(index)) => (index + 1) % layouts.len(),
+            (Direction::Previous, Some(index)) => (index + layouts.len() - 1) % layouts.len(),
+            (Direction::Next, None) => 0,
+            (Direction::Previous, None) => layouts.len().checked_sub(1)?,
+        };
+
+        layouts.get(index).map(String::as_str)
     }
 
-    /// Find the next occupied tag in the direction
-    fn find_next_occupied(
+    /// Cycle the tagmask in either next or previous direction, only stopping at tags that pass the filter.
+    /// Every tag moves a number of steps, wrapping around at the edges if allowed.
+    /// Fails if none of the focused tags are within the number of tags, or if a tag would have to wrap around
+    /// when that isn't allowed. Only tags within the number of tags are moved, any tags above it are kept as they are.
+    pub fn cycle_tags(
         &self,
-        occupied_tags: &[u8],
-        direction: &str,
+        direction: Direction,
+        n_tags: TagCount,
+        filter: TagFilter,
+        steps: u8,
+        wrap: bool,
+    ) -> Result<TagMask, String> {
+        let tags = self.focused_tags.unwrap_or_default();
+        let cycled = tags & n_tags.mask();
+
+        if cycled.is_empty() {
+            return Err(String::from(
+                "None of the focused tags are within the number of tags",
+            ));
+        }
+
+        let occupied_tags = self.find_set_bits_positions(n_tags);
+        let empty_tags: Vec<u8> = (n_tags.mask() - self.occupied_mask()).iter().collect();
+
+        // Only skip unoccupied tags if there are more than one occupied tag, and empty ones if there are any
+        let candidates = match filter {
+            TagFilter::Occupied if occupied_tags.len() > 1 => occupied_tags,
+            TagFilter::Empty if !empty_tags.is_empty() => empty_tags,
+            _ => (0..=n_tags.last()).collect(),
+        };
+
+        let mut new_tags = TagMask::default();
+        for tag_index in cycled.iter() {
+            new_tags |= TagMask::single(
+                Self::find_next_candidate(&candidates, direction, tag_index, steps, wrap)
+                    .ok_or_else(|| match direction {
+                        Direction::Next => {
+                            String::from("There are no more tags after the focused tags")
+                        }
+                        Direction::Previous => {
+                            String::from("There are no more tags before the focused tags")
+                        }
+                    })?,
+            );
+        }
+
+        Ok(new_tags | (tags - n_tags.mask()))
+    }
+
+    /// Find the candidate tag a number of steps away in the direction, going on from the other end
+    /// of the candidates if allowed. The candidates are sorted from the lowest tag.
+    fn find_next_candidate(
+        candidates: &[u8],
+        direction: Direction,
         tag_index: u8,
+        steps: u8,
+        wrap: bool,
     ) -> Option<u8> {
-        let mut result: Option<u8> = None;
-
-        // Go over each tag in the occupied tags
-        for &occupied_tag_index in occupied_tags.iter() {
-            // If we cycle next the tag index should be greater than the one we're looking up.
-            // Otherwise it should be smaller.
-            let condition = if direction == "next" {
-                occupied_tag_index > tag_index
-            } else {
-                occupied_tag_index < tag_index
-            };
-
-            // Then find the closest occupied tag according to direction by using min/max function on each tag and replace the value.
-            // This could be None, which is fine, and will be unhandled with an unwrap to a default tag position.
-            if condition {
-                result = Some(result.map_or(occupied_tag_index, |next_occupied| {
-                    if direction == "next" {
-                        u8::min(next_occupied, occupied_tag_index)
-                    } else {
-                        u8::max(next_occupied, occupied_tag_index)
-                    }
-                }));
-            }
+        let in_direction: Vec<u8> = match direction {
+            Direction::Next => candidates.to_vec(),
+            Direction::Previous => candidates.iter().rev().copied().collect(),
+        };
+
+        // The candidates up to the edge, in the order they are reached
+        let ahead: Vec<u8> = in_direction
+            .iter()
+            .copied()
+            .filter(|&candidate| match direction {
+                Direction::Next => candidate > tag_index,
+                Direction::Previous => candidate < tag_index,
+            })
+            .collect();
+
+        let steps = usize::from(steps);
+        if let Some(&candidate) = ahead.get(steps.checked_sub(1)?) {
+            return Some(candidate);
+        }
+        if !wrap || in_direction.is_empty() {
+            return None;
         }
 
-        result
+        // Past the edge, go round every candidate as often as needed
+        Some(in_direction[(steps - ahead.len() - 1) % in_direction.len()])
     }
 
-    /// Find the indices of set bits
-    fn find_set_bits_positions(&self, n_tags: u8) -> Vec<u8> {
-        let mut result = Vec::new();
-
-        // Iterate over 4-byte chunks of the occupied tags
-        for chunk in self.occupied_tags.chunks(4) {
-            // Then iterate over bytes in the current chunk, along with their indices
-            for (byte_index, &byte) in chunk.iter().enumerate() {
-                // And iterate over bits in the current byte
-                for bit_index in 0..8 {
-                    // Check if the bit at bit_index is set = occupied tag
-                    if (byte & (1 << bit_index)) != 0 {
-                        // If set, calculate the overall bit index
-                        let index = (byte_index * 8 + bit_index) as u8;
-                        // Only keep it if it's within the number of tags
-                        if index < n_tags {
-                            result.push(index);
-                        }
-                    }
-                }
-            }
-        }
+    /// Find the first tag without views within the number of tags.
+    /// If a tag index is given, the search starts after it and wraps around.
+    pub fn find_empty_tag(&self, n_tags: TagCount, after: Option<u8>) -> Option<u8> {
+        let occupied_tags = self.find_set_bits_positions(n_tags);
+        let start = after.map_or(0, |index| index + 1);
 
-        result
+        (start..=n_tags.last())
+            .chain(0..start)
+            .find(|index| !occupied_tags.contains(index))
     }
+
+    /// Find the indices of occupied tags within the number of tags
+    pub fn find_set_bits_positions(&self, n_tags: TagCount) -> Vec<u8> {
+        (self.occupied_mask() & n_tags.mask()).iter().collect()
+    }
+
+    /// Combine the tags of every view on the output into a single tagmask
+    pub fn occupied_mask(&self) -> TagMask {
+        self.occupied_tags
+            .chunks_exact(4)
+            .map(|chunk| TagMask::new(u32::from_ne_bytes([chunk[0], chunk[1], chunk[2], chunk[3]])))
+            .fold(TagMask::default(), |mask, tags| mask | tags)
+    }
+
     /// Checks if the requested tags are already focused
-    pub fn toggle_tags(&self, to_tags: &u32) -> bool {
-        self.focused_tags == Some(*to_tags)
+    pub fn toggle_tags(&self, to_tags: TagMask) -> bool {
+        self.focused_tags == Some(to_tags)
     }
 }
@@ -0,0 +1,107 @@
+use crate::client::Flow;
+use crate::config::Config;
+use crate::format::Format;
+use crate::output::Output;
+use crate::watch::{json_option, output_fields};
+
+/// A snapshot of the state flow sees, either as a table, as a JSON object or in the given format.
+/// If an output name is given, only that output is included.
+pub fn snapshot(
+    flow: &Flow,
+    config: &Config,
+    only_output: Option<&str>,
+    json: bool,
+    format: Option<&Format>,
+) -> Result<String, String> {
+    if let Some(name) = only_output {
+        flow.target_output(Some(name))?;
+    }
+
+    let outputs: Vec<&Output> = flow
+        .outputs
+        .iter()
+        .filter(|output| only_output.is_none_or(|name| output.name == name))
+        .collect();
+
+    if let Some(format) = format {
+        return Ok(format
+            .render(flow, config, &outputs)
+            .into_iter()
+            .map(|(_, line)| line + "\n")
+            .collect());
+    }
+
+    Ok(match json {
+        true => snapshot_json(flow, config, &outputs),
+        false => snapshot_table(flow, &outputs),
+    })
+}
+
+fn snapshot_json(flow: &Flow, config: &Config, outputs: &[&Output]) -> String {
+    let seat = flow.seat();
+
+    let outputs: Vec<String> = outputs
+        .iter()
+        .map(|output| format!("{{{}}}", output_fields(flow, output, config)))
+        .collect();
+
+    format!(
+        r#"{{"outputs":[{}],"seat":{},"focused_view":{},"mode":{}}}"#,
+        outputs.join(","),
+        json_option(seat.and_then(|seat| seat.name.as_deref())),
+        json_option(seat.and_then(|seat| seat.focused_view.as_deref())),
+        json_option(seat.and_then(|seat| seat.mode.as_deref())),
+    ) + "\n"
+}
+
+fn snapshot_table(flow: &Flow, outputs: &[&Output]) -> String {
+    let seat = flow.seat();
+
+    let mut rows = vec![[
+        String::from("OUTPUT"),
+        String::from("FOCUSED"),
+        String::from("FOCUSED TAGS"),
+        String::from("OCCUPIED TAGS"),
+        String::from("URGENT TAGS"),
+        String::from("LAYOUT"),
+    ]];
+    for output in outputs {
+        rows.push([
+            output.name.to_owned(),
+            String::from(if flow.is_focused(output) { "yes" } else { "no" }),
+            output.focused_tags.unwrap_or_default().to_string(),
+            output.occupied_mask().to_string(),
+            output.urgent_tags.unwrap_or_default().to_string(),
+            output
+                .layout_name
+                .clone()
+                .unwrap_or_else(|| String::from("-")),
+        ]);
+    }
+
+    // Pad every column but the last to its widest value
+    let widths: Vec<usize> = (0..5)
+        .map(|column| rows.iter().map(|row| row[column].len()).max().unwrap_or(0))
+        .collect();
+
+    let mut table = String::new();
+    for row in &rows {
+        let mut line = String::new();
+        for (column, width) in widths.iter().enumerate() {
+            line.push_str(&format!("{:width$}  ", row[column], width = width));
+        }
+        line.push_str(&row[5]);
+        table.push_str(line.trim_end());
+        table.push('\n');
+    }
+
+    let value = |value: Option<&str>| value.unwrap_or("-").to_owned();
+    table.push_str(&format!(
+        "\nSeat: {}\nFocused view: {}\nMode: {}\n",
+        value(seat.and_then(|seat| seat.name.as_deref())),
+        value(seat.and_then(|seat| seat.focused_view.as_deref())),
+        value(seat.and_then(|seat| seat.mode.as_deref())),
+    ));
+
+    table
+}
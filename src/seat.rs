@@ -1,17 +1,28 @@
 use crate::protocols::river_protocols::zriver_seat_status_v1::ZriverSeatStatusV1;
+use wayland_client::backend::ObjectId;
 use wayland_client::protocol::wl_seat::WlSeat;
 
-#[derive(Debug)]
+#[derive(Clone, Debug)]
 pub struct Seat {
     pub wlseat: WlSeat,
+    /// The name from the wl_seat name event
+    pub name: Option<String>,
     pub seat_status: Option<ZriverSeatStatusV1>,
+    /// The wloutput id of the output this seat has focused
+    pub focused_output: Option<ObjectId>,
+    pub focused_view: Option<String>,
+    pub mode: Option<String>,
 }
 
 impl Seat {
     pub fn new(wlseat: WlSeat) -> Self {
         Self {
             wlseat,
+            name: None,
             seat_status: None,
+            focused_output: None,
+            focused_view: None,
+            mode: None,
         }
     }
 }
@@ -0,0 +1,325 @@
+use std::fmt;
+use std::ops::{BitAnd, BitAndAssign, BitOr, BitOrAssign, Not, Sub};
+use std::str::FromStr;
+
+use serde::Deserialize;
+
+use crate::output::Output;
+
+/// A 32-bit bitfield of tags, as river uses for focused tags, view tags and urgent tags
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub struct TagMask(u32);
+
+impl TagMask {
+    pub const fn new(bits: u32) -> Self {
+        Self(bits)
+    }
+
+    /// A mask with only the tag at the given index set
+    pub fn single(index: u8) -> Self {
+        Self(1 << index)
+    }
+
+    pub fn is_empty(self) -> bool {
+        self.0 == 0
+    }
+
+    pub fn contains(self, index: u8) -> bool {
+        index < 32 && (self.0 >> index) & 1 == 1
+    }
+
+    /// The indices of the set tags, lowest first
+    pub fn iter(self) -> impl Iterator<Item = u8> {
+        (0..32).filter(move |&index| self.contains(index))
+    }
+
+    /// Move every tag within the number of tags by a number of steps, wrapping around at the edges.
+    /// Positive steps move towards higher tags. Tags above the number of tags are dropped.
+    pub fn rotate(self, steps: i32, n_tags: TagCount) -> Self {
+        let count = i32::from(n_tags.0);
+        // Reduce the steps first, so any number of steps can be added to an index without overflowing
+        let steps = steps.rem_euclid(count);
+
+        (self & n_tags.mask())
+            .iter()
+            .map(|index| (i32::from(index) + steps).rem_euclid(count) as u8)
+            .fold(Self::default(), |mask, index| mask | Self::single(index))
+    }
+}
+
+impl fmt::Display for TagMask {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+/// Parse a tagmask from the command line. An empty mask is rejected, since focusing no tags makes no sense.
+impl FromStr for TagMask {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.parse::<u32>() {
+            Ok(0) => Err(String::from("The tagmask must have at least one tag set")),
+            Ok(bits) => Ok(Self(bits)),
+            Err(_) => Err(format!("Invalid tagmask: {}", s)),
+        }
+    }
+}
+
+impl BitOr for TagMask {
+    type Output = Self;
+
+    fn bitor(self, rhs: Self) -> Self {
+        Self(self.0 | rhs.0)
+    }
+}
+
+impl BitOrAssign for TagMask {
+    fn bitor_assign(&mut self, rhs: Self) {
+        self.0 |= rhs.0;
+    }
+}
+
+impl BitAnd for TagMask {
+    type Output = Self;
+
+    fn bitand(self, rhs: Self) -> Self {
+        Self(self.0 & rhs.0)
+    }
+}
+
+impl BitAndAssign for TagMask {
+    fn bitand_assign(&mut self, rhs: Self) {
+        self.0 &= rhs.0;
+    }
+}
+
+impl Not for TagMask {
+    type Output = Self;
+
+    fn not(self) -> Self {
+        Self(!self.0)
+    }
+}
+
+/// The tags set in the left mask but not in the right one
+impl Sub for TagMask {
+    type Output = Self;
+
+    fn sub(self, rhs: Self) -> Self {
+        Self(self.0 & !rhs.0)
+    }
+}
+
+/// The number of tags in use, between 1 and 32
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize)]
+#[serde(try_from = "u8")]
+pub struct TagCount(u8);
+
+impl TagCount {
+    pub fn new(count: u8) -> Result<Self, String> {
+        match count {
+            1..=32 => Ok(Self(count)),
+            _ => Err(format!(
+                "The number of tags must be between 1 and 32, got {}",
+                count
+            )),
+        }
+    }
+
+    /// Index of the last tag
+    pub fn last(self) -> u8 {
+        self.0 - 1
+    }
+
+    /// A mask with every tag in use set
+    pub fn mask(self) -> TagMask {
+        TagMask(u32::MAX >> (32 - self.0))
+    }
+}
+
+/// River defaults to 9 tags bound to keys
+impl Default for TagCount {
+    fn default() -> Self {
+        Self(9)
+    }
+}
+
+impl TryFrom<u8> for TagCount {
+    type Error = String;
+
+    fn try_from(count: u8) -> Result<Self, Self::Error> {
+        Self::new(count)
+    }
+}
+
+impl FromStr for TagCount {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let count = s
+            .parse::<u8>()
+            .map_err(|_| format!("Invalid number of tags: {}", s))?;
+
+        Self::new(count)
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Direction {
+    Next,
+    Previous,
+}
+
+impl FromStr for Direction {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "next" => Ok(Self::Next),
+            "previous" => Ok(Self::Previous),
+            _ => Err(format!(
+                "Unknown direction: {}, expected next or previous",
+                s
+            )),
+        }
+    }
+}
+
+/// Which tags to stop at when cycling
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TagFilter {
+    All,
+    Occupied,
+    Empty,
+}
+
+/// Tags given on the command line, resolved against the state of an output when the command is run.
+///
+/// A comma separated list where each item is one of:
+/// - a 1-based tag index or range of tags, e.g. `3` or `4-6`
+/// - the focused, occupied or urgent tags of the output, optionally moved by a number of steps, e.g. `focused+1`
+/// - a raw tagmask, e.g. `mask:64`
+/// - a tag name from the config, e.g. `web`
+///
+/// Any item can be prefixed with `~` to take every other tag instead.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct TagExpression(Vec<TagTerm>);
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+struct TagTerm {
+    inverted: bool,
+    tags: TagSource,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+enum TagSource {
+    Mask(TagMask),
+    Focused(i32),
+    Occupied(i32),
+    Urgent(i32),
+}
+
+impl TagExpression {
+    /// Parse tags from the command line, looking up any tag names in the names from the config
+    pub fn parse(s: &str, names: &[String]) -> Result<Self, String> {
+        s.split(',')
+            .map(|item| TagTerm::parse(item, names))
+            .collect::<Result<_, _>>()
+            .map(Self)
+    }
+
+    /// Work out the tagmask for an output. Fails if state is needed but there's no output, or if no tags are left.
+    pub fn resolve(&self, output: Option<&Output>, n_tags: TagCount) -> Result<TagMask, String> {
+        let mut result = TagMask::default();
+
+        for term in &self.0 {
+            let state = |select: fn(&Output) -> TagMask| {
+                output
+                    .map(select)
+                    .ok_or_else(|| String::from("There is no output to take the tags from"))
+            };
+
+            let tags = match term.tags {
+                TagSource::Mask(mask) => mask,
+                TagSource::Focused(steps) => {
+                    state(|output| output.focused_tags.unwrap_or_default())?.rotate(steps, n_tags)
+                }
+                TagSource::Occupied(steps) => {
+                    state(|output| output.occupied_mask())?.rotate(steps, n_tags)
+                }
+                TagSource::Urgent(steps) => {
+                    state(|output| output.urgent_tags.unwrap_or_default())?.rotate(steps, n_tags)
+                }
+            };
+
+            result |= if term.inverted {
+                n_tags.mask() - tags
+            } else {
+                tags
+            };
+        }
+
+        if result.is_empty() {
+            return Err(String::from("The tags given don't match any tag"));
+        }
+
+        Ok(result)
+    }
+}
+
+impl TagTerm {
+    fn parse(item: &str, names: &[String]) -> Result<Self, String> {
+        let (inverted, item) = match item.strip_prefix('~') {
+            Some(item) => (true, item),
+            None => (false, item),
+        };
+
+        let tags = if let Some(mask) = item.strip_prefix("mask:") {
+            TagSource::Mask(mask.parse()?)
+        } else if let Some(index) = names.iter().position(|name| name == item) {
+            TagSource::Mask(TagMask::single(index as u8))
+        } else if item.starts_with(|c: char| c.is_ascii_alphabetic()) {
+            // A state keyword, optionally followed by a number of steps
+            let split = item.find(['+', '-']).unwrap_or(item.len());
+            let (keyword, steps) = item.split_at(split);
+            let steps = match steps {
+                "" => 0,
+                steps => steps
+                    .parse()
+                    .map_err(|_| format!("Invalid number of steps in: {}", item))?,
+            };
+
+            match keyword {
+                "focused" => TagSource::Focused(steps),
+                "occupied" => TagSource::Occupied(steps),
+                "urgent" => TagSource::Urgent(steps),
+                _ => return Err(format!("Unknown tags: {}", keyword)),
+            }
+        } else {
+            let (first, last) = item.split_once('-').unwrap_or((item, item));
+            let (first, last) = (parse_index(first)?, parse_index(last)?);
+
+            if first > last {
+                return Err(format!("Invalid range of tags: {}", item));
+            }
+
+            TagSource::Mask((first..=last).fold(TagMask::default(), |mask, index| {
+                mask | TagMask::single(index)
+            }))
+        };
+
+        Ok(Self { inverted, tags })
+    }
+}
+
+/// Turn a 1-based tag number into a tag index
+fn parse_index(tag: &str) -> Result<u8, String> {
+    match tag.parse::<u8>() {
+        Ok(tag @ 1..=32) => Ok(tag - 1),
+        _ => Err(format!(
+            "Invalid tag: {}, expected a number between 1 and 32",
+            tag
+        )),
+    }
+}
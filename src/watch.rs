@@ -0,0 +1,141 @@
+use std::collections::HashMap;
+use std::error::Error;
+use std::io::Write;
+
+use wayland_client::EventQueue;
+
+use crate::client::Flow;
+use crate::config::Config;
+use crate::format::Format;
+use crate::output::Output;
+use crate::tags::TagMask;
+
+/// Keep dispatching events and print the state of an output as a JSON object on its own line whenever it changes.
+/// If an output name is given, only that output is printed. A format replaces the JSON object.
+pub fn run(
+    event_queue: &mut EventQueue<Flow>,
+    flow: &mut Flow,
+    config: &Config,
+    only_output: Option<&str>,
+    format: Option<&Format>,
+) -> Result<(), Box<dyn Error>> {
+    let mut last_printed: HashMap<String, String> = HashMap::new();
+    let mut stdout = std::io::stdout().lock();
+
+    // The i3bar protocol starts with a header and then an endless array of status lines
+    if let Some(Format::I3bar) = format {
+        writeln!(stdout, "{{\"version\":1}}\n[")?;
+    }
+
+    loop {
+        flow.bind_status(&event_queue.handle());
+
+        let outputs: Vec<&Output> = flow
+            .outputs
+            .iter()
+            .filter(|output| only_output.is_none_or(|name| output.name == name))
+            .collect();
+
+        let lines: Vec<(String, String)> = match format {
+            Some(Format::I3bar) => Format::I3bar
+                .render(flow, config, &outputs)
+                .into_iter()
+                .map(|(name, line)| (name, line + ","))
+                .collect(),
+            Some(format) => format.render(flow, config, &outputs),
+            None => outputs
+                .iter()
+                .map(|output| (output.name.to_owned(), output_json(flow, output, config)))
+                .collect(),
+        };
+
+        for (name, line) in &lines {
+            if last_printed.get(name) != Some(line) {
+                writeln!(stdout, "{}", line)?;
+                last_printed.insert(name.to_owned(), line.to_owned());
+            }
+        }
+
+        // Forget outputs that are gone, so they are printed again if they come back
+        last_printed.retain(|name, _| lines.iter().any(|(printed, _)| printed == name));
+
+        // Status bars read line by line, so don't keep anything buffered
+        stdout.flush()?;
+
+        event_queue.blocking_dispatch(flow)?;
+    }
+}
+
+/// The state of an output along with the seat state, which is repeated for each output
+fn output_json(flow: &Flow, output: &Output, config: &Config) -> String {
+    let seat = flow.seat();
+
+    format!(
+        r#"{{{},"focused_output":{},"focused_view":{},"mode":{}}}"#,
+        output_fields(flow, output, config),
+        json_option(
+            flow.find_output("focused")
+                .map(|output| output.name.as_str())
+        ),
+        json_option(seat.and_then(|seat| seat.focused_view.as_deref())),
+        json_option(seat.and_then(|seat| seat.mode.as_deref())),
+    )
+}
+
+/// The JSON fields for the state of an output, shared by watch and query so both use the same keys
+pub fn output_fields(flow: &Flow, output: &Output, config: &Config) -> String {
+    let focused_tags = output.focused_tags.unwrap_or_default();
+    let occupied_tags = output.occupied_mask();
+    let urgent_tags = output.urgent_tags.unwrap_or_default();
+
+    format!(
+        r#""output":{},"focused":{},"focused_tags":{},"occupied_tags":{},"urgent_tags":{},"focused_tag_names":{},"occupied_tag_names":{},"urgent_tag_names":{},"layout":{}"#,
+        json_string(&output.name),
+        flow.is_focused(output),
+        focused_tags,
+        occupied_tags,
+        urgent_tags,
+        tag_names_json(focused_tags, config),
+        tag_names_json(occupied_tags, config),
+        tag_names_json(urgent_tags, config),
+        json_option(output.layout_name.as_deref()),
+    )
+}
+
+/// The names of the tags in a mask as a JSON array, using the tag number for tags without a name
+fn tag_names_json(tags: TagMask, config: &Config) -> String {
+    let names: Vec<String> = tags
+        .iter()
+        .map(|index| json_string(&config.tag_name(index)))
+        .collect();
+
+    format!("[{}]", names.join(","))
+}
+
+/// Quote and escape a string for use in JSON
+pub fn json_string(value: &str) -> String {
+    let mut result = String::with_capacity(value.len() + 2);
+    result.push('"');
+
+    for character in value.chars() {
+        match character {
+            '"' => result.push_str("\\\""),
+            '\\' => result.push_str("\\\\"),
+            '\n' => result.push_str("\\n"),
+            '\r' => result.push_str("\\r"),
+            '\t' => result.push_str("\\t"),
+            character if character.is_control() => {
+                result.push_str(&format!("\\u{:04x}", character as u32))
+            }
+            character => result.push(character),
+        }
+    }
+
+    result.push('"');
+    result
+}
+
+/// A JSON string, or null if there is no value
+pub fn json_option(value: Option<&str>) -> String {
+    value.map_or_else(|| String::from("null"), json_string)
+}
@@ -0,0 +1,1489 @@
+mod common;
+
+use std::io::{BufRead, BufReader, Write};
+use std::process::Stdio;
+use std::thread;
+use std::time::Duration;
+
+use common::{Mock, MockOutput, MockSeat, Script, command, lines, two_outputs};
+
+#[test]
+fn cycle_tags_next_and_previous() {
+    let mock = Mock::start(two_outputs());
+
+    assert!(mock.flow(&["cycle-tags", "next"]).status.success());
+    assert!(mock.flow(&["cycle-tags", "previous", "4"]).status.success());
+
+    assert_eq!(
+        mock.commands(),
+        vec![
+            command(&["set-focused-tags", "2"]),
+            command(&["set-focused-tags", "1"]),
+        ]
+    );
+}
+
+#[test]
+fn cycle_tags_wraps_around() {
+    let mock = Mock::start(two_outputs());
+
+    mock.flow(&["cycle-tags", "previous", "4"]);
+
+    assert_eq!(mock.commands(), vec![command(&["set-focused-tags", "8"])]);
+}
+
+#[test]
+fn cycle_tags_skips_unoccupied() {
+    let mock = Mock::start(two_outputs());
+
+    assert!(
+        mock.flow(&["cycle-tags", "next", "--occupied"])
+            .status
+            .success()
+    );
+    assert!(
+        mock.flow(&["cycle-tags", "next", "9", "-o"])
+            .status
+            .success()
+    );
+
+    assert_eq!(
+        mock.commands(),
+        vec![
+            command(&["set-focused-tags", "4"]),
+            command(&["set-focused-tags", "1"]),
+        ]
+    );
+}
+
+#[test]
+fn cycle_tags_in_steps_without_wrapping() {
+    let mock = Mock::start(two_outputs());
+
+    let output = mock.flow(&["cycle-tags", "previous", "--no-wrap"]);
+    assert!(!output.status.success());
+    assert_eq!(
+        lines(&output.stderr),
+        vec!["Error: There are no more tags before the focused tags"]
+    );
+
+    assert!(
+        mock.flow(&["cycle-tags", "next", "--step", "3"])
+            .status
+            .success()
+    );
+    assert!(
+        !mock
+            .flow(&["cycle-tags", "next", "4", "--no-wrap"])
+            .status
+            .success()
+    );
+    assert!(
+        mock.flow(&["cycle-tags", "next", "4", "--step", "2"])
+            .status
+            .success()
+    );
+    assert!(
+        !mock
+            .flow(&["cycle-tags", "next", "--step", "0"])
+            .status
+            .success()
+    );
+
+    assert_eq!(
+        mock.commands(),
+        vec![
+            command(&["set-focused-tags", "8"]),
+            command(&["set-focused-tags", "2"]),
+        ]
+    );
+}
+
+#[test]
+fn cycle_occupied_tags_in_steps_without_wrapping() {
+    let mut script = two_outputs();
+    script.outputs[0].view_tags = vec![0b1, 0b100, 0b10_0000];
+    let mock = Mock::start(script);
+
+    for (args, success) in [
+        (&["cycle-tags", "next", "-o", "--step", "2"][..], true),
+        (&["cycle-tags", "next", "-o", "--no-wrap"], false),
+        (&["cycle-tags", "next", "-o", "--step", "2"], true),
+        (
+            &["cycle-tags", "previous", "-o", "--step", "2", "--no-wrap"],
+            false,
+        ),
+        (&["cycle-tags", "previous", "-o", "--no-wrap"], true),
+    ] {
+        assert_eq!(mock.flow(args).status.success(), success, "{:?}", args);
+    }
+
+    assert_eq!(
+        mock.commands(),
+        vec![
+            command(&["set-focused-tags", "32"]),
+            command(&["set-focused-tags", "4"]),
+            command(&["set-focused-tags", "1"]),
+        ]
+    );
+}
+
+#[test]
+fn cycle_tags_keeps_tags_above_the_tag_count() {
+    let mut script = two_outputs();
+    script.outputs[0].focused_tags = 0b1_0000_0001;
+    let mock = Mock::start(script);
+
+    mock.flow(&["cycle-tags", "previous", "4"]);
+
+    assert_eq!(mock.commands(), vec![command(&["set-focused-tags", "264"])]);
+}
+
+#[test]
+fn invalid_input_is_rejected() {
+    let mock = Mock::start(two_outputs());
+
+    for args in [
+        vec!["cycle-tags", "sideways"],
+        vec!["cycle-tags", "next", "0"],
+        vec!["cycle-tags", "next", "33"],
+        vec!["toggle-tags", "0"],
+        vec!["focus-set-view-tags", "tag"],
+    ] {
+        let output = mock.flow(&args);
+        assert!(!output.status.success(), "{:?} should fail", args);
+    }
+
+    // Nothing focused within the first two tags
+    mock.update(|script| script.outputs[0].focused_tags = 0b100);
+    assert!(!mock.flow(&["cycle-tags", "next", "2"]).status.success());
+
+    assert!(mock.commands().is_empty());
+}
+
+#[test]
+fn toggle_tags_focuses_or_goes_back() {
+    let mock = Mock::start(two_outputs());
+
+    mock.flow(&["toggle-tags", "3"]);
+    mock.flow(&["toggle-tags", "3"]);
+
+    assert_eq!(
+        mock.commands(),
+        vec![
+            command(&["set-focused-tags", "4"]),
+            command(&["focus-previous-tags"]),
+        ]
+    );
+}
+
+#[test]
+fn focus_urgent_tags() {
+    let mut script = two_outputs();
+    script.outputs[1].urgent_tags = 0b1000;
+    let mock = Mock::start(script);
+
+    mock.flow(&["focus-urgent-tags"]);
+
+    assert_eq!(
+        mock.commands(),
+        vec![
+            command(&["focus-output", "HDMI-A-1"]),
+            command(&["set-focused-tags", "8"]),
+        ]
+    );
+}
+
+#[test]
+fn focus_urgent_tags_without_urgency() {
+    let mock = Mock::start(two_outputs());
+
+    assert!(mock.flow(&["focus-urgent-tags"]).status.success());
+
+    assert!(mock.commands().is_empty());
+}
+
+#[test]
+fn focus_set_view_tags() {
+    let mock = Mock::start(two_outputs());
+
+    mock.flow(&["focus-set-view-tags", "5"]);
+
+    assert_eq!(
+        mock.commands(),
+        vec![
+            command(&["set-view-tags", "16"]),
+            command(&["set-focused-tags", "16"]),
+        ]
+    );
+}
+
+#[test]
+fn tag_expressions() {
+    let mut script = two_outputs();
+    script.outputs[0].urgent_tags = 0b10;
+    let mock = Mock::start(script);
+
+    for expression in [
+        "1,4-6",
+        "focused+3",
+        "focused-1",
+        "~focused",
+        "occupied",
+        "urgent,9",
+        "~1-8",
+        "mask:64",
+    ] {
+        assert!(
+            mock.flow(&["focus-set-view-tags", expression])
+                .status
+                .success(),
+            "{} should resolve",
+            expression
+        );
+    }
+
+    let focused_tags: Vec<Vec<String>> = mock
+        .commands()
+        .into_iter()
+        .filter(|arguments| arguments[0] == "set-focused-tags")
+        .collect();
+
+    // Each command is resolved against the tags set by the one before it
+    assert_eq!(
+        focused_tags,
+        vec![
+            command(&["set-focused-tags", "57"]),
+            command(&["set-focused-tags", "456"]),
+            command(&["set-focused-tags", "228"]),
+            command(&["set-focused-tags", "283"]),
+            // The focused view was moved to 283, the other view is on tag 3
+            command(&["set-focused-tags", "287"]),
+            command(&["set-focused-tags", "258"]),
+            command(&["set-focused-tags", "256"]),
+            command(&["set-focused-tags", "64"]),
+        ]
+    );
+}
+
+#[test]
+fn tag_expressions_with_many_steps() {
+    let mock = Mock::start(two_outputs());
+
+    // 2147483647 is 1 more than a multiple of 9, and -2147483648 is 2 less
+    mock.flow(&["toggle-tags", "focused+2147483647"]);
+    mock.flow(&["toggle-tags", "--output", "HDMI-A-1", "focused-2147483648"]);
+
+    assert_eq!(
+        mock.commands(),
+        vec![
+            command(&["set-focused-tags", "2"]),
+            command(&["focus-output", "HDMI-A-1"]),
+            command(&["set-focused-tags", "256"]),
+            command(&["focus-output", "DP-1"]),
+        ]
+    );
+}
+
+#[test]
+fn invalid_tag_expressions() {
+    let mock = Mock::start(two_outputs());
+
+    for expression in [
+        "0",
+        "33",
+        "5-2",
+        "1,",
+        "focus",
+        "focused+x",
+        "mask:0",
+        "urgent",
+    ] {
+        let output = mock.flow(&["toggle-tags", expression]);
+        assert!(!output.status.success(), "{} should fail", expression);
+    }
+
+    assert!(mock.commands().is_empty());
+}
+
+#[test]
+fn failed_command_exits_non_zero() {
+    let mut script = two_outputs();
+    script
+        .failing_commands
+        .push(String::from("set-focused-tags"));
+    let mock = Mock::start(script);
+
+    let output = mock.flow(&["cycle-tags", "next"]);
+
+    assert!(!output.status.success());
+    assert_eq!(
+        lines(&output.stderr),
+        vec!["Error: set-focused-tags failed"]
+    );
+}
+
+#[test]
+fn command_output_is_printed() {
+    let mut script = two_outputs();
+    script
+        .command_output
+        .insert(String::from("set-focused-tags"), String::from("done"));
+    let mock = Mock::start(script);
+
+    let output = mock.flow(&["toggle-tags", "8"]);
+
+    assert!(output.status.success());
+    assert_eq!(lines(&output.stdout), vec!["done"]);
+}
+
+#[test]
+fn unknown_subcommand_fails() {
+    let mock = Mock::start(two_outputs());
+
+    let output = mock.flow(&["does-not-exist"]);
+
+    assert!(!output.status.success());
+    assert!(mock.commands().is_empty());
+}
+
+#[test]
+fn watch_prints_state_changes() {
+    let mock = Mock::start(two_outputs());
+
+    let mut watch = mock
+        .command(&["watch"])
+        .stdout(Stdio::piped())
+        .spawn()
+        .expect("Failed to start watch");
+    let mut stdout = BufReader::new(watch.stdout.take().unwrap()).lines();
+
+    let mut next_line = || stdout.next().unwrap().unwrap();
+
+    assert_eq!(
+        next_line(),
+        r#"{"output":"DP-1","focused":true,"focused_tags":1,"occupied_tags":5,"urgent_tags":0,"focused_tag_names":["1"],"occupied_tag_names":["1","3"],"urgent_tag_names":[],"layout":null,"focused_output":"DP-1","focused_view":"terminal","mode":"normal"}"#
+    );
+    assert_eq!(
+        next_line(),
+        r#"{"output":"HDMI-A-1","focused":false,"focused_tags":2,"occupied_tags":2,"urgent_tags":0,"focused_tag_names":["2"],"occupied_tag_names":["2"],"urgent_tag_names":[],"layout":null,"focused_output":"DP-1","focused_view":"terminal","mode":"normal"}"#
+    );
+
+    mock.update(|script| script.outputs[1].focused_tags = 0b110);
+
+    assert_eq!(
+        next_line(),
+        r#"{"output":"HDMI-A-1","focused":false,"focused_tags":6,"occupied_tags":2,"urgent_tags":0,"focused_tag_names":["2","3"],"occupied_tag_names":["2"],"urgent_tag_names":[],"layout":null,"focused_output":"DP-1","focused_view":"terminal","mode":"normal"}"#
+    );
+
+    watch.kill().unwrap();
+    watch.wait().unwrap();
+}
+
+#[test]
+fn daemon_serves_commands_with_current_state() {
+    let mock = Mock::start(two_outputs());
+
+    let mut daemon = mock.daemon();
+
+    assert!(mock.flow(&["cycle-tags", "next"]).status.success());
+    assert!(mock.flow(&["cycle-tags", "next"]).status.success());
+
+    // A second daemon for the same display is refused
+    assert!(!mock.flow(&["daemon"]).status.success());
+
+    daemon.kill().unwrap();
+    daemon.wait().unwrap();
+
+    // The daemon kept track of the tags focused by the first command
+    assert_eq!(
+        mock.commands(),
+        vec![
+            command(&["set-focused-tags", "2"]),
+            command(&["set-focused-tags", "4"]),
+        ]
+    );
+
+    // With the daemon gone, the command is run directly
+    assert!(mock.flow(&["toggle-tags", "1"]).status.success());
+    assert_eq!(mock.wait_for_commands(3).len(), 3);
+}
+
+#[test]
+fn output_option_targets_another_output() {
+    let mock = Mock::start(two_outputs());
+
+    assert!(
+        mock.flow(&["--output", "HDMI-A-1", "cycle-tags", "next"])
+            .status
+            .success()
+    );
+    // The focused output needs no focus changes
+    assert!(
+        mock.flow(&["toggle-tags", "3", "--output", "DP-1"])
+            .status
+            .success()
+    );
+
+    assert_eq!(
+        mock.commands(),
+        vec![
+            command(&["focus-output", "HDMI-A-1"]),
+            command(&["set-focused-tags", "4"]),
+            command(&["focus-output", "DP-1"]),
+            command(&["set-focused-tags", "4"]),
+        ]
+    );
+}
+
+#[test]
+fn output_option_rejects_unknown_outputs() {
+    let mock = Mock::start(two_outputs());
+
+    let output = mock.flow(&["--output", "VGA-1", "cycle-tags", "next"]);
+
+    assert!(!output.status.success());
+    assert_eq!(
+        lines(&output.stderr),
+        vec!["Error: There is no output named VGA-1"]
+    );
+    assert!(mock.commands().is_empty());
+}
+
+#[test]
+fn seat_option_uses_the_focus_of_that_seat() {
+    let mut script = two_outputs();
+    script.other_seats.push(MockSeat {
+        name: String::from("seat1"),
+        focused_output: 1,
+    });
+    let mock = Mock::start(script);
+
+    assert!(mock.flow(&["cycle-tags", "next"]).status.success());
+    assert!(
+        mock.flow(&["--seat", "seat1", "cycle-tags", "next"])
+            .status
+            .success()
+    );
+
+    let script = mock.script();
+    assert_eq!(
+        script.commands,
+        vec![
+            command(&["set-focused-tags", "2"]),
+            command(&["set-focused-tags", "4"]),
+        ]
+    );
+    assert_eq!(script.command_seats, vec!["seat0", "seat1"]);
+    // Each seat changed the tags of its own focused output
+    assert_eq!(script.outputs[0].focused_tags, 0b10);
+    assert_eq!(script.outputs[1].focused_tags, 0b100);
+
+    let output = mock.flow(&["--seat", "seat2", "cycle-tags", "next"]);
+    assert!(!output.status.success());
+    assert_eq!(
+        lines(&output.stderr),
+        vec!["Error: There is no seat named seat2"]
+    );
+}
+
+#[test]
+fn back_and_forward_through_the_tag_history() {
+    let mock = Mock::start(two_outputs());
+    let mut daemon = mock.daemon();
+
+    for args in [
+        &["cycle-tags", "next"][..],
+        &["cycle-tags", "next"],
+        &["back"],
+        &["back"],
+        &["forward"],
+    ] {
+        assert!(
+            mock.flow(args).status.success(),
+            "{:?} should succeed",
+            args
+        );
+    }
+
+    // Nothing before the tags focused when the daemon started
+    mock.flow(&["back"]);
+    mock.flow(&["back"]);
+    let output = mock.flow(&["back"]);
+    assert!(!output.status.success());
+    assert_eq!(
+        lines(&output.stderr),
+        vec!["Error: There are no earlier tags to go back to"]
+    );
+
+    // Focusing other tags drops everything that could be gone forward to
+    mock.flow(&["toggle-tags", "5"]);
+    assert!(!mock.flow(&["forward"]).status.success());
+
+    daemon.kill().unwrap();
+    daemon.wait().unwrap();
+
+    assert_eq!(
+        mock.commands(),
+        vec![
+            command(&["set-focused-tags", "2"]),
+            command(&["set-focused-tags", "4"]),
+            command(&["set-focused-tags", "2"]),
+            command(&["set-focused-tags", "1"]),
+            command(&["set-focused-tags", "2"]),
+            command(&["set-focused-tags", "1"]),
+            command(&["set-focused-tags", "16"]),
+        ]
+    );
+}
+
+#[test]
+fn focus_urgent_tags_moves_through_urgent_outputs() {
+    let mock = Mock::start(two_outputs());
+    let mut daemon = mock.daemon();
+
+    // HDMI-A-1 becomes urgent before DP-1
+    mock.update(|script| script.outputs[1].urgent_tags = 0b1000);
+    thread::sleep(Duration::from_millis(50));
+    mock.update(|script| script.outputs[0].urgent_tags = 0b100);
+    thread::sleep(Duration::from_millis(50));
+
+    assert!(mock.flow(&["focus-urgent-tags"]).status.success());
+    assert!(mock.flow(&["focus-urgent-tags"]).status.success());
+    assert!(
+        mock.flow(&["focus-urgent-tags", "--return"])
+            .status
+            .success()
+    );
+    // Only one jump to return from
+    assert!(
+        !mock
+            .flow(&["focus-urgent-tags", "--return"])
+            .status
+            .success()
+    );
+
+    // Urgency that went away is forgotten
+    mock.update(|script| {
+        script.outputs[0].urgent_tags = 0;
+        script.outputs[1].urgent_tags = 0;
+    });
+    thread::sleep(Duration::from_millis(50));
+    assert!(mock.flow(&["focus-urgent-tags"]).status.success());
+
+    daemon.kill().unwrap();
+    daemon.wait().unwrap();
+
+    assert_eq!(
+        mock.commands(),
+        vec![
+            command(&["focus-output", "HDMI-A-1"]),
+            command(&["set-focused-tags", "8"]),
+            command(&["focus-output", "DP-1"]),
+            command(&["set-focused-tags", "4"]),
+            command(&["focus-output", "DP-1"]),
+            command(&["set-focused-tags", "1"]),
+        ]
+    );
+}
+
+#[test]
+fn focus_urgent_tags_returns_after_urgency_is_cleared() {
+    let mock = Mock::start(two_outputs());
+    let mut daemon = mock.daemon();
+
+    mock.update(|script| script.outputs[1].urgent_tags = 0b1000);
+    thread::sleep(Duration::from_millis(50));
+    mock.update(|script| script.outputs[0].urgent_tags = 0b100);
+    thread::sleep(Duration::from_millis(50));
+
+    assert!(mock.flow(&["focus-urgent-tags"]).status.success());
+    // River clears the urgency once the urgent view is focused
+    mock.update(|script| script.outputs[1].urgent_tags = 0);
+    thread::sleep(Duration::from_millis(50));
+    assert!(mock.flow(&["focus-urgent-tags"]).status.success());
+    assert!(
+        mock.flow(&["focus-urgent-tags", "--return"])
+            .status
+            .success()
+    );
+
+    daemon.kill().unwrap();
+    daemon.wait().unwrap();
+
+    assert_eq!(
+        mock.commands(),
+        vec![
+            command(&["focus-output", "HDMI-A-1"]),
+            command(&["set-focused-tags", "8"]),
+            command(&["focus-output", "DP-1"]),
+            command(&["set-focused-tags", "4"]),
+            command(&["focus-output", "DP-1"]),
+            command(&["set-focused-tags", "1"]),
+        ]
+    );
+}
+
+#[test]
+fn cycle_layout() {
+    let mut script = two_outputs();
+    // rivertile shows its layout as []= rather than its namespace
+    script.outputs[0].layout = Some(String::from("[]="));
+    let mock = Mock::start(script);
+
+    // Without knowing the namespace, the layout in use isn't found in the list
+    assert!(
+        mock.flow(&["cycle-layout", "next", "rivertile", "monocle"])
+            .status
+            .success()
+    );
+    mock.write_config("[layout-namespaces]\n\"[]=\" = \"rivertile\"");
+
+    for args in [
+        &["cycle-layout", "next", "rivertile", "monocle"][..],
+        &["cycle-layout", "next", "rivertile", "monocle"],
+        &["cycle-layout", "previous", "rivertile", "monocle"],
+        // Not in the list, so start from the first one
+        &["cycle-layout", "next", "stacktile", "filtile"],
+    ] {
+        assert!(
+            mock.flow(args).status.success(),
+            "{:?} should succeed",
+            args
+        );
+    }
+
+    assert!(!mock.flow(&["cycle-layout", "next"]).status.success());
+
+    assert_eq!(
+        mock.commands(),
+        vec![
+            command(&["output-layout", "rivertile"]),
+            command(&["output-layout", "monocle"]),
+            command(&["output-layout", "rivertile"]),
+            command(&["output-layout", "monocle"]),
+            command(&["output-layout", "stacktile"]),
+        ]
+    );
+}
+
+#[test]
+fn daemon_restores_the_layout_of_tags() {
+    let mut script = two_outputs();
+    script.outputs[0].layout = Some(String::from("[]="));
+    let mock = Mock::start(script);
+    mock.write_config("[layout-namespaces]\n\"[]=\" = \"rivertile\"");
+    let mut daemon = mock.daemon_with(&["--restore-layouts"]);
+
+    for args in [
+        &["toggle-tags", "2"][..],
+        &["cycle-layout", "next", "rivertile", "monocle"],
+        &["toggle-tags", "2"],
+        &["toggle-tags", "2"],
+    ] {
+        assert!(
+            mock.flow(args).status.success(),
+            "{:?} should succeed",
+            args
+        );
+    }
+
+    mock.wait_for_commands(6);
+    // Leave time for a layout to be restored more than once
+    thread::sleep(Duration::from_millis(100));
+    let commands = mock.commands();
+    daemon.kill().unwrap();
+    daemon.wait().unwrap();
+
+    assert_eq!(
+        commands,
+        vec![
+            command(&["set-focused-tags", "2"]),
+            command(&["output-layout", "monocle"]),
+            command(&["focus-previous-tags"]),
+            command(&["output-layout", "rivertile"]),
+            command(&["set-focused-tags", "2"]),
+            command(&["output-layout", "monocle"]),
+        ]
+    );
+}
+
+#[test]
+fn daemon_restores_the_layout_of_tags_focused_without_a_layout_change() {
+    let mut script = two_outputs();
+    script.outputs[0].layout = Some(String::from("[]="));
+    let mock = Mock::start(script);
+    mock.write_config("[layout-namespaces]\n\"[]=\" = \"rivertile\"");
+    let mut daemon = mock.daemon_with(&["--restore-layouts"]);
+
+    // Tag 3 only ever shows the layout it was focused with, river doesn't send the layout name again
+    for args in [
+        &["toggle-tags", "3"][..],
+        &["toggle-tags", "2"],
+        &["cycle-layout", "next", "rivertile", "monocle"],
+        &["toggle-tags", "3"],
+    ] {
+        assert!(
+            mock.flow(args).status.success(),
+            "{:?} should succeed",
+            args
+        );
+    }
+
+    mock.wait_for_commands(5);
+    // Leave time for a layout to be restored more than once
+    thread::sleep(Duration::from_millis(100));
+    let commands = mock.commands();
+    daemon.kill().unwrap();
+    daemon.wait().unwrap();
+
+    assert_eq!(
+        commands,
+        vec![
+            command(&["set-focused-tags", "4"]),
+            command(&["set-focused-tags", "2"]),
+            command(&["output-layout", "monocle"]),
+            command(&["set-focused-tags", "4"]),
+            command(&["output-layout", "rivertile"]),
+        ]
+    );
+}
+
+#[test]
+fn cycle_view_tags() {
+    let mock = Mock::start(two_outputs());
+
+    for args in [
+        &["cycle-view-tags", "next"][..],
+        &["cycle-view-tags", "previous", "4"],
+        &["cycle-view-tags", "next", "--occupied"],
+        &["cycle-view-tags", "next", "--empty", "--follow"],
+    ] {
+        assert!(
+            mock.flow(args).status.success(),
+            "{:?} should succeed",
+            args
+        );
+    }
+    assert!(
+        mock.flow(&["cycle-view-tags", "next", "--step", "3"])
+            .status
+            .success()
+    );
+    for args in [
+        &["cycle-view-tags", "next", "--occupied", "--empty"][..],
+        &["cycle-view-tags", "next", "2", "--no-wrap"],
+    ] {
+        assert!(!mock.flow(args).status.success(), "{:?} should fail", args);
+    }
+
+    // The focused tags stay on tag 1 until --follow, tag 2 is the first empty tag after it
+    assert_eq!(
+        mock.commands(),
+        vec![
+            command(&["set-view-tags", "2"]),
+            command(&["set-view-tags", "8"]),
+            command(&["set-view-tags", "4"]),
+            command(&["set-view-tags", "2"]),
+            command(&["set-focused-tags", "2"]),
+            command(&["set-view-tags", "16"]),
+        ]
+    );
+}
+
+#[test]
+fn swap_output_tags() {
+    let mut script = two_outputs();
+    script.outputs.push(MockOutput::new("DP-2", 0b1000, &[]));
+    let mock = Mock::start(script);
+
+    // There's more than one output to swap with
+    assert!(!mock.flow(&["swap-output-tags"]).status.success());
+    assert!(
+        !mock
+            .flow(&["swap-output-tags", "--with", "DP-1"])
+            .status
+            .success()
+    );
+
+    assert!(
+        mock.flow(&["swap-output-tags", "--with", "HDMI-A-1"])
+            .status
+            .success()
+    );
+
+    let script = mock.script();
+    assert_eq!(
+        script.commands,
+        vec![
+            command(&["set-focused-tags", "2"]),
+            command(&["focus-output", "HDMI-A-1"]),
+            command(&["set-focused-tags", "1"]),
+            command(&["focus-output", "DP-1"]),
+        ]
+    );
+    assert_eq!(script.focused_output, 0);
+    assert_eq!(script.outputs[0].focused_tags, 0b10);
+    assert_eq!(script.outputs[1].focused_tags, 0b1);
+}
+
+#[test]
+fn focus_empty_tag() {
+    let mut script = two_outputs();
+    script.outputs[0].view_tags = vec![0b1, 0b10, 0b1000];
+    let mock = Mock::start(script);
+
+    for args in [
+        &["focus-empty-tag"][..],
+        &["focus-empty-tag", "--after-current"],
+        &["focus-empty-tag", "--send-view"],
+    ] {
+        assert!(
+            mock.flow(args).status.success(),
+            "{:?} should succeed",
+            args
+        );
+    }
+
+    assert_eq!(
+        mock.commands(),
+        vec![
+            command(&["set-focused-tags", "4"]),
+            command(&["set-focused-tags", "16"]),
+            command(&["set-view-tags", "4"]),
+            command(&["set-focused-tags", "4"]),
+        ]
+    );
+}
+
+#[test]
+fn focus_empty_tag_with_every_tag_occupied() {
+    let mock = Mock::start(two_outputs());
+    mock.write_config("tags = 3");
+    mock.update(|script| script.outputs[0].view_tags = vec![0b1, 0b10, 0b100]);
+
+    let output = mock.flow(&["focus-empty-tag"]);
+
+    assert!(!output.status.success());
+    assert_eq!(
+        lines(&output.stderr),
+        vec!["Error: Every tag is occupied, there is no empty tag to focus"]
+    );
+}
+
+#[test]
+fn focus_and_send_to_occupied_tags() {
+    let mut script = two_outputs();
+    script.outputs[0].view_tags = vec![0b1, 0b100, 0b10000];
+    let mock = Mock::start(script);
+
+    assert!(mock.flow(&["focus-occupied", "3"]).status.success());
+    assert!(mock.flow(&["send-occupied", "2"]).status.success());
+
+    // The focused view left tag 1, so only tags 3 and 5 are occupied
+    let output = mock.flow(&["focus-occupied", "3"]);
+    assert_eq!(
+        lines(&output.stderr),
+        vec!["Error: There is no occupied tag 3, only 2 tags are occupied"]
+    );
+    assert!(!mock.flow(&["focus-occupied", "0"]).status.success());
+
+    assert_eq!(
+        mock.commands(),
+        vec![
+            command(&["set-focused-tags", "16"]),
+            command(&["set-view-tags", "4"]),
+        ]
+    );
+}
+
+#[test]
+fn query_prints_a_snapshot() {
+    let mut script = two_outputs();
+    script.outputs[0].layout = Some(String::from("rivertile"));
+    script.outputs[1].urgent_tags = 0b100;
+    let mock = Mock::start(script);
+
+    let output = mock.flow(&["query"]);
+    assert!(output.status.success());
+    assert_eq!(
+        lines(&output.stdout),
+        vec![
+            "OUTPUT    FOCUSED  FOCUSED TAGS  OCCUPIED TAGS  URGENT TAGS  LAYOUT",
+            "DP-1      yes      1             5              0            rivertile",
+            "HDMI-A-1  no       2             2              4            -",
+            "",
+            "Seat: seat0",
+            "Focused view: terminal",
+            "Mode: normal",
+        ]
+    );
+
+    let output = mock.flow(&["query", "--json", "--output", "HDMI-A-1"]);
+    assert_eq!(
+        lines(&output.stdout),
+        vec![
+            r#"{"outputs":[{"output":"HDMI-A-1","focused":false,"focused_tags":2,"occupied_tags":2,"urgent_tags":4,"focused_tag_names":["2"],"occupied_tag_names":["2"],"urgent_tag_names":["3"],"layout":null}],"seat":"seat0","focused_view":"terminal","mode":"normal"}"#
+        ]
+    );
+
+    assert!(!mock.flow(&["query", "--output", "VGA-1"]).status.success());
+    assert!(mock.commands().is_empty());
+}
+
+#[test]
+fn chained_commands_run_in_order() {
+    let mock = Mock::start(two_outputs());
+
+    let output = mock.flow(&[
+        "cycle-tags",
+        "next",
+        ";",
+        "toggle-tags",
+        "focused+1",
+        ";",
+        "--output",
+        "HDMI-A-1",
+        "cycle-tags",
+        "next",
+    ]);
+
+    assert!(output.status.success());
+    // Each command sees the tags focused by the one before it
+    assert_eq!(
+        mock.commands(),
+        vec![
+            command(&["set-focused-tags", "2"]),
+            command(&["set-focused-tags", "4"]),
+            command(&["focus-output", "HDMI-A-1"]),
+            command(&["set-focused-tags", "4"]),
+            command(&["focus-output", "DP-1"]),
+        ]
+    );
+}
+
+#[test]
+fn chained_commands_see_tags_river_has_not_reported_yet() {
+    let mut script = two_outputs();
+    script.status_delay = Some(Duration::from_millis(500));
+    let mock = Mock::start(script);
+
+    let output = mock.flow(&[
+        "cycle-tags",
+        "next",
+        ";",
+        "cycle-tags",
+        "next",
+        ";",
+        "--output",
+        "HDMI-A-1",
+        "toggle-tags",
+        "3",
+        ";",
+        "--output",
+        "HDMI-A-1",
+        "toggle-tags",
+        "3",
+    ]);
+
+    assert!(output.status.success());
+    assert_eq!(
+        mock.commands(),
+        vec![
+            command(&["set-focused-tags", "2"]),
+            command(&["set-focused-tags", "4"]),
+            command(&["focus-output", "HDMI-A-1"]),
+            command(&["set-focused-tags", "4"]),
+            command(&["focus-output", "DP-1"]),
+            command(&["focus-output", "HDMI-A-1"]),
+            command(&["focus-previous-tags"]),
+            command(&["focus-output", "DP-1"]),
+        ]
+    );
+}
+
+#[test]
+fn options_before_chained_commands_apply_to_every_command() {
+    let mut script = two_outputs();
+    script
+        .failing_commands
+        .push(String::from("focus-previous-tags"));
+    let mock = Mock::start(script);
+
+    let output = mock.flow(&[
+        "--output",
+        "HDMI-A-1",
+        "batch",
+        "--stop-on-error",
+        "toggle-tags",
+        "3",
+        ";",
+        "toggle-tags",
+        "4",
+        ";",
+        "toggle-tags",
+        "4",
+        ";",
+        "toggle-tags",
+        "5",
+    ]);
+    assert!(!output.status.success());
+
+    // A ; after -- is passed on to river
+    assert!(
+        mock.flow(&["run", "--", "spawn", "foo", ";", "bar"])
+            .status
+            .success()
+    );
+
+    assert_eq!(
+        mock.commands(),
+        vec![
+            command(&["focus-output", "HDMI-A-1"]),
+            command(&["set-focused-tags", "4"]),
+            command(&["focus-output", "DP-1"]),
+            command(&["focus-output", "HDMI-A-1"]),
+            command(&["set-focused-tags", "8"]),
+            command(&["focus-output", "DP-1"]),
+            command(&["focus-output", "HDMI-A-1"]),
+            command(&["focus-previous-tags"]),
+            command(&["focus-output", "DP-1"]),
+            command(&["spawn", "foo", ";", "bar"]),
+        ]
+    );
+
+    // Nothing is run when the whole chain is a dry run, whatever the order of the options
+    let mock = Mock::start(two_outputs());
+    let output = mock.flow(&[
+        "--dry-run",
+        "--output",
+        "HDMI-A-1",
+        "toggle-tags",
+        "3",
+        ";",
+        "toggle-tags",
+        "4",
+    ]);
+    assert!(output.status.success());
+    assert_eq!(
+        lines(&output.stdout),
+        vec![
+            "focus-output HDMI-A-1",
+            "set-focused-tags 4",
+            "focus-output DP-1",
+            "focus-output HDMI-A-1",
+            "set-focused-tags 8",
+            "focus-output DP-1"
+        ]
+    );
+    assert!(mock.commands().is_empty());
+}
+
+#[test]
+fn batch_reads_commands_from_stdin() {
+    let mut script = two_outputs();
+    script
+        .failing_commands
+        .push(String::from("focus-previous-tags"));
+    let mock = Mock::start(script);
+
+    let run = |args: &[&str], input: &str| {
+        let mut batch = mock
+            .command(args)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .expect("Failed to start the batch");
+        batch
+            .stdin
+            .take()
+            .unwrap()
+            .write_all(input.as_bytes())
+            .unwrap();
+        batch.wait_with_output().unwrap()
+    };
+
+    let input = "# Comments and empty lines are skipped\n\ntoggle-tags 1\ntoggle-tags 2\n";
+
+    let output = run(&["batch", "--stop-on-error"], input);
+    assert!(!output.status.success());
+    assert_eq!(
+        lines(&output.stderr),
+        vec!["Error: focus-previous-tags failed"]
+    );
+    assert_eq!(mock.commands(), vec![command(&["focus-previous-tags"])]);
+
+    // Without stopping the rest still runs, but the exit status reports the failure
+    let output = run(&["batch"], input);
+    assert!(!output.status.success());
+    assert_eq!(
+        mock.commands()[1..],
+        [
+            command(&["focus-previous-tags"]),
+            command(&["set-focused-tags", "2"]),
+        ]
+    );
+
+    // Options given to the batch apply to every command
+    let output = run(&["--output", "HDMI-A-1", "batch"], "toggle-tags 3\n");
+    assert!(output.status.success());
+    assert_eq!(
+        mock.commands()[3..],
+        [
+            command(&["focus-output", "HDMI-A-1"]),
+            command(&["set-focused-tags", "4"]),
+            command(&["focus-output", "DP-1"]),
+        ]
+    );
+
+    // Nothing runs if any command is invalid
+    let output = run(&["batch"], "toggle-tags 1\ncycle-tags sideways\n");
+    assert_eq!(
+        lines(&output.stderr),
+        vec![
+            "Error: Command 2: failed to parse 'sideways': Unknown direction: sideways, expected next or previous"
+        ]
+    );
+    assert_eq!(mock.commands().len(), 6);
+}
+
+#[test]
+fn batch_reads_commands_from_a_file() {
+    let mock = Mock::start(two_outputs());
+    let file = mock.runtime_dir().join("commands");
+    std::fs::write(&file, "toggle-tags 3\nfocus-set-view-tags 5\n").unwrap();
+
+    // The batch is forwarded to the daemon like any other command
+    let mut daemon = mock.daemon();
+    let output = mock.flow(&["batch", "--file", file.to_str().unwrap()]);
+    daemon.kill().unwrap();
+    daemon.wait().unwrap();
+
+    assert!(output.status.success());
+
+    // Quoted arguments are kept whole, also after -- on a line before other commands
+    std::fs::write(
+        &file,
+        "run -- spawn \"foot -e 'htop'\" ;\nrun -- send-layout-cmd rivertile 'main-ratio 0.6'\ntoggle-tags 1\n",
+    )
+    .unwrap();
+    let mut daemon = mock.daemon();
+    let output = mock.flow(&["batch", "--file", file.to_str().unwrap()]);
+    assert!(output.status.success());
+
+    std::fs::write(&file, "run -- spawn \"foot\n").unwrap();
+    let output = mock.flow(&["batch", "--file", file.to_str().unwrap()]);
+    daemon.kill().unwrap();
+    daemon.wait().unwrap();
+    assert_eq!(
+        lines(&output.stderr),
+        vec!["Error: Line 1: Missing closing quote"]
+    );
+
+    assert_eq!(
+        mock.commands(),
+        vec![
+            command(&["set-focused-tags", "4"]),
+            command(&["set-view-tags", "16"]),
+            command(&["set-focused-tags", "16"]),
+            command(&["spawn", "foot -e 'htop'", ";"]),
+            command(&["send-layout-cmd", "rivertile", "main-ratio 0.6"]),
+            command(&["set-focused-tags", "1"]),
+        ]
+    );
+}
+
+#[test]
+fn run_passes_commands_to_river() {
+    let mut script = two_outputs();
+    script
+        .command_output
+        .insert(String::from("list-inputs"), String::from("keyboard-1\n"));
+    script.failing_commands.push(String::from("bogus"));
+    let mock = Mock::start(script);
+
+    let output = mock.flow(&["run", "--", "list-inputs"]);
+    assert!(output.status.success());
+    assert_eq!(lines(&output.stdout), vec!["keyboard-1"]);
+
+    // Options after -- belong to river
+    assert!(
+        mock.flow(&[
+            "--output",
+            "HDMI-A-1",
+            "run",
+            "--",
+            "send-layout-cmd",
+            "rivertile",
+            "--output",
+            "x"
+        ])
+        .status
+        .success()
+    );
+
+    let output = mock.flow(&["run", "--", "bogus"]);
+    assert!(!output.status.success());
+    assert_eq!(lines(&output.stderr), vec!["Error: bogus failed"]);
+
+    assert!(!mock.flow(&["run"]).status.success());
+    assert!(!mock.flow(&["toggle-tags", "1", "--", "x"]).status.success());
+
+    assert_eq!(
+        mock.commands(),
+        vec![
+            command(&["list-inputs"]),
+            command(&["focus-output", "HDMI-A-1"]),
+            command(&["send-layout-cmd", "rivertile", "--output", "x"]),
+            command(&["focus-output", "DP-1"]),
+            command(&["bogus"]),
+        ]
+    );
+
+    // No output is needed, e.g. early in the init before any output is there
+    let mock = Mock::start(Script::new(vec![]));
+    assert!(mock.flow(&["run", "--", "spawn", "foot"]).status.success());
+    assert_eq!(mock.commands(), vec![command(&["spawn", "foot"])]);
+}
+
+#[test]
+fn dry_run_prints_commands_without_running_them() {
+    let mock = Mock::start(two_outputs());
+
+    let output = mock.flow(&["--dry-run", "cycle-tags", "next"]);
+    assert!(output.status.success());
+    assert_eq!(lines(&output.stdout), vec!["set-focused-tags 2"]);
+
+    let output = mock.flow(&["toggle-tags", "3", "--output", "HDMI-A-1", "--dry-run"]);
+    assert_eq!(
+        lines(&output.stdout),
+        vec![
+            "focus-output HDMI-A-1",
+            "set-focused-tags 4",
+            "focus-output DP-1"
+        ]
+    );
+
+    let output = mock.flow(&["--dry-run", "run", "--", "spawn", "foot -e htop"]);
+    assert_eq!(lines(&output.stdout), vec!["spawn 'foot -e htop'"]);
+
+    // Errors are still reported
+    assert!(
+        !mock
+            .flow(&["--dry-run", "--output", "VGA-1", "cycle-tags", "next"])
+            .status
+            .success()
+    );
+
+    // Every command of a batch is a dry run, also when forwarded to the daemon
+    let file = mock.runtime_dir().join("commands");
+    std::fs::write(&file, "toggle-tags 2\nrun -- list-inputs\n").unwrap();
+    let mut daemon = mock.daemon();
+    let output = mock.flow(&["--dry-run", "batch", "--file", file.to_str().unwrap()]);
+    daemon.kill().unwrap();
+    daemon.wait().unwrap();
+    assert_eq!(
+        lines(&output.stdout),
+        vec!["set-focused-tags 2", "list-inputs"]
+    );
+
+    // The same goes for chained commands
+    let output = mock.flow(&[
+        "--dry-run",
+        "toggle-tags",
+        "2",
+        ";",
+        "--output",
+        "HDMI-A-1",
+        "toggle-tags",
+        "3",
+    ]);
+    assert!(output.status.success());
+    assert_eq!(
+        lines(&output.stdout),
+        vec![
+            "set-focused-tags 2",
+            "focus-output HDMI-A-1",
+            "set-focused-tags 4",
+            "focus-output DP-1"
+        ]
+    );
+
+    // Each command is worked out from the state the one before would leave, which the daemon forgets afterwards
+    let mut daemon = mock.daemon();
+    let output = mock.flow(&["--dry-run", "cycle-tags", "next", ";", "cycle-tags", "next"]);
+    assert_eq!(
+        lines(&output.stdout),
+        vec!["set-focused-tags 2", "set-focused-tags 4"]
+    );
+    let output = mock.flow(&["--dry-run", "cycle-tags", "next"]);
+    daemon.kill().unwrap();
+    daemon.wait().unwrap();
+    assert_eq!(lines(&output.stdout), vec!["set-focused-tags 2"]);
+
+    assert!(mock.commands().is_empty());
+}
+
+#[test]
+fn dry_run_leaves_the_tag_history_alone() {
+    let mock = Mock::start(two_outputs());
+    let mut daemon = mock.daemon();
+
+    mock.flow(&["toggle-tags", "2"]);
+    let output = mock.flow(&["back", "--dry-run"]);
+    assert_eq!(lines(&output.stdout), vec!["set-focused-tags 1"]);
+    assert!(mock.flow(&["back"]).status.success());
+
+    daemon.kill().unwrap();
+    daemon.wait().unwrap();
+
+    assert_eq!(
+        mock.commands(),
+        vec![
+            command(&["set-focused-tags", "2"]),
+            command(&["set-focused-tags", "1"]),
+        ]
+    );
+}
+
+#[test]
+fn explain_prints_the_state_used() {
+    let mut script = two_outputs();
+    script.outputs[1].urgent_tags = 0b100;
+    let mock = Mock::start(script);
+
+    let output = mock.flow(&[
+        "--explain",
+        "--output",
+        "HDMI-A-1",
+        "cycle-tags",
+        "next",
+        "4",
+    ]);
+    assert!(output.status.success());
+    assert_eq!(
+        lines(&output.stdout),
+        vec![
+            "Focused output: DP-1",
+            "Output: HDMI-A-1",
+            "Focused tags: 2",
+            "Occupied tags: 2",
+            "Urgent tags: 4",
+            "Number of tags: 4",
+            "Commands:",
+            "focus-output HDMI-A-1",
+            "set-focused-tags 4",
+            "focus-output DP-1",
+        ]
+    );
+
+    // Unlike a dry run, the commands are run
+    assert_eq!(
+        mock.commands(),
+        vec![
+            command(&["focus-output", "HDMI-A-1"]),
+            command(&["set-focused-tags", "4"]),
+            command(&["focus-output", "DP-1"]),
+        ]
+    );
+}
+
+#[test]
+fn query_with_a_format() {
+    let mut script = two_outputs();
+    script.outputs[0].layout = Some(String::from("rivertile"));
+    script.outputs[1].urgent_tags = 0b100;
+    let mock = Mock::start(script);
+
+    let output = mock.flow(&[
+        "query",
+        "--format",
+        "{output} {layout}:{#tags}{!empty} {?focused}[{/focused}{name}{?focused}]{/focused}{/empty}{?urgent} !{index}{/urgent}{/tags}",
+    ]);
+    assert!(output.status.success());
+    assert_eq!(
+        lines(&output.stdout),
+        vec!["DP-1 rivertile: [1] 3", "HDMI-A-1 : [2] !3"]
+    );
+
+    let output = mock.flow(&[
+        "query",
+        "--output",
+        "DP-1",
+        "--format",
+        "{{{?focused}*{/focused}{#tags}{state} {/tags}}}",
+    ]);
+    assert_eq!(
+        lines(&output.stdout),
+        vec!["{*focused empty occupied empty empty empty empty empty empty }"]
+    );
+
+    let output = mock.flow(&["query", "--format", "waybar"]);
+    assert_eq!(
+        lines(&output.stdout),
+        vec![
+            r#"{"text":"<b>1</b> 3","tooltip":"DP-1","class":["focused"]}"#,
+            r#"{"text":"<b>2</b> <u>3</u>","tooltip":"HDMI-A-1","class":["urgent"]}"#,
+        ]
+    );
+
+    let output = mock.flow(&["query", "--format", "i3bar", "--output", "HDMI-A-1"]);
+    assert_eq!(
+        lines(&output.stdout),
+        vec![
+            r#"[{"name":"flow","instance":"HDMI-A-1:2","full_text":"<b>2</b>","markup":"pango","urgent":false},{"name":"flow","instance":"HDMI-A-1:3","full_text":"<u>3</u>","markup":"pango","urgent":true}]"#
+        ]
+    );
+
+    for format in [
+        "json",
+        "{tags}",
+        "{index}",
+        "{#tags}{name}",
+        "{?focused}{/tags}",
+        "{?empty}x{/empty}",
+        "{#tags}{#tags}{/tags}{/tags}",
+        "}",
+    ] {
+        let output = mock.flow(&["query", "--format", format]);
+        assert!(!output.status.success(), "{} should be rejected", format);
+    }
+    assert!(
+        !mock
+            .flow(&["query", "--json", "--format", "waybar"])
+            .status
+            .success()
+    );
+}
+
+#[test]
+fn watch_with_the_i3bar_format() {
+    let mock = Mock::start(two_outputs());
+
+    let mut watch = mock
+        .command(&["watch", "--format", "i3bar", "--output", "HDMI-A-1"])
+        .stdout(Stdio::piped())
+        .spawn()
+        .expect("Failed to start watch");
+    let mut stdout = BufReader::new(watch.stdout.take().unwrap()).lines();
+
+    let mut next_line = || stdout.next().unwrap().unwrap();
+
+    assert_eq!(next_line(), r#"{"version":1}"#);
+    assert_eq!(next_line(), "[");
+    assert_eq!(
+        next_line(),
+        r#"[{"name":"flow","instance":"HDMI-A-1:2","full_text":"<b>2</b>","markup":"pango","urgent":false}],"#
+    );
+
+    mock.update(|script| script.outputs[1].focused_tags = 0b1000);
+
+    assert_eq!(
+        next_line(),
+        r#"[{"name":"flow","instance":"HDMI-A-1:2","full_text":"2","markup":"pango","urgent":false},{"name":"flow","instance":"HDMI-A-1:4","full_text":"<b>4</b>","markup":"pango","urgent":false}],"#
+    );
+
+    watch.kill().unwrap();
+    watch.wait().unwrap();
+}
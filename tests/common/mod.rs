@@ -0,0 +1,694 @@
+//! A fake river compositor for end-to-end tests.
+//!
+//! It advertises the globals flow binds, sends the state described by a [`Script`] to every
+//! status object and records each command it receives. A few commands are applied to the state,
+//! so that a long-running flow process sees the result of the commands it sent.
+#![allow(dead_code)]
+
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::process::{Child, Command, Output};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+use wayland_server::backend::{ClientData, ClientId, ObjectId};
+use wayland_server::protocol::{wl_output::WlOutput, wl_seat::WlSeat};
+use wayland_server::{
+    Client, DataInit, Dispatch, Display, DisplayHandle, GlobalDispatch, ListeningSocket, New,
+    Resource,
+};
+
+use protocols::{
+    zriver_command_callback_v1::ZriverCommandCallbackV1,
+    zriver_control_v1::{self, ZriverControlV1},
+    zriver_output_status_v1::{self, ZriverOutputStatusV1},
+    zriver_seat_status_v1::ZriverSeatStatusV1,
+    zriver_status_manager_v1::{self, ZriverStatusManagerV1},
+};
+
+pub mod protocols {
+    use wayland_server;
+    use wayland_server::protocol::*;
+
+    #[allow(non_upper_case_globals)]
+    pub mod __status {
+        use wayland_server::backend as wayland_backend;
+        use wayland_server::protocol::__interfaces::*;
+        wayland_scanner::generate_interfaces!("./resources/river-status-unstable-v1.xml");
+    }
+
+    #[allow(non_upper_case_globals)]
+    pub mod __control {
+        use wayland_server::backend as wayland_backend;
+        use wayland_server::protocol::__interfaces::*;
+        wayland_scanner::generate_interfaces!("./resources/river-control-unstable-v1.xml");
+    }
+    use self::__control::*;
+    use self::__status::*;
+
+    wayland_scanner::generate_server_code!("./resources/river-status-unstable-v1.xml");
+    wayland_scanner::generate_server_code!("./resources/river-control-unstable-v1.xml");
+}
+
+/// The state of a single output as river would report it
+#[derive(Clone, Debug, Default)]
+pub struct MockOutput {
+    pub name: String,
+    pub focused_tags: u32,
+    /// The tags of each view on the output, the first one is the focused view
+    pub view_tags: Vec<u32>,
+    pub urgent_tags: u32,
+    pub layout: Option<String>,
+    /// Used to answer focus-previous-tags
+    pub previous_tags: u32,
+}
+
+impl MockOutput {
+    pub fn new(name: &str, focused_tags: u32, view_tags: &[u32]) -> Self {
+        Self {
+            name: name.to_owned(),
+            focused_tags,
+            view_tags: view_tags.to_vec(),
+            ..Default::default()
+        }
+    }
+}
+
+/// A seat besides the default seat0
+#[derive(Clone, Debug)]
+pub struct MockSeat {
+    pub name: String,
+    /// Index of the output focused by this seat
+    pub focused_output: usize,
+}
+
+/// Everything the fake compositor reports, along with the commands it has received
+#[derive(Clone, Debug)]
+pub struct Script {
+    pub outputs: Vec<MockOutput>,
+    /// Index of the output focused by seat0
+    pub focused_output: usize,
+    /// Seats advertised after seat0
+    pub other_seats: Vec<MockSeat>,
+    pub focused_view: String,
+    pub mode: String,
+    /// Commands with these names are answered with a failure
+    pub failing_commands: Vec<String>,
+    /// Commands answered with this output on success
+    pub command_output: HashMap<String, String>,
+    pub commands: Vec<Vec<String>>,
+    /// The name of the seat each command was run on
+    pub command_seats: Vec<String>,
+    /// The layout name shown by each layout generator, by namespace. Others show their namespace.
+    pub layout_names: HashMap<String, String>,
+    /// Send the output status this long after a command instead of right away, like river does once the new state is shown
+    pub status_delay: Option<Duration>,
+}
+
+impl Script {
+    pub fn new(outputs: Vec<MockOutput>) -> Self {
+        Self {
+            outputs,
+            focused_output: 0,
+            other_seats: vec![],
+            focused_view: String::from("terminal"),
+            mode: String::from("normal"),
+            failing_commands: vec![],
+            command_output: HashMap::new(),
+            commands: vec![],
+            command_seats: vec![],
+            layout_names: HashMap::from([(String::from("rivertile"), String::from("[]="))]),
+            status_delay: None,
+        }
+    }
+
+    /// The name of a seat by index, where 0 is seat0
+    fn seat_name(&self, seat: usize) -> String {
+        match seat {
+            0 => String::from("seat0"),
+            _ => self.other_seats[seat - 1].name.to_owned(),
+        }
+    }
+
+    fn seat_focus(&mut self, seat: usize) -> &mut usize {
+        match seat {
+            0 => &mut self.focused_output,
+            _ => &mut self.other_seats[seat - 1].focused_output,
+        }
+    }
+
+    /// Apply the commands that change state flow keeps track of. Everything else is only recorded.
+    fn apply(&mut self, arguments: &[String], seat: usize) {
+        let focused = *self.seat_focus(seat);
+        let parse = |index: usize| arguments.get(index).and_then(|tags| tags.parse().ok());
+
+        match arguments.first().map(String::as_str) {
+            Some("set-focused-tags") => {
+                if let (Some(tags), Some(output)) = (parse(1), self.outputs.get_mut(focused)) {
+                    output.previous_tags = output.focused_tags;
+                    output.focused_tags = tags;
+                }
+            }
+            Some("focus-previous-tags") => {
+                if let Some(output) = self.outputs.get_mut(focused) {
+                    std::mem::swap(&mut output.focused_tags, &mut output.previous_tags);
+                }
+            }
+            Some("set-view-tags") => {
+                if let (Some(tags), Some(output)) = (parse(1), self.outputs.get_mut(focused))
+                    && let Some(view) = output.view_tags.first_mut()
+                {
+                    *view = tags;
+                }
+            }
+            Some("focus-output") => {
+                if let Some(index) = self
+                    .outputs
+                    .iter()
+                    .position(|output| Some(&output.name) == arguments.get(1))
+                {
+                    *self.seat_focus(seat) = index;
+                }
+            }
+            Some("output-layout") => {
+                let layout = arguments.get(1).map(|namespace| {
+                    self.layout_names
+                        .get(namespace)
+                        .unwrap_or(namespace)
+                        .to_owned()
+                });
+                if let Some(output) = self.outputs.get_mut(focused) {
+                    output.layout = layout;
+                }
+            }
+            _ => (),
+        }
+    }
+}
+
+/// A running fake compositor, stopped when dropped
+pub struct Mock {
+    script: Arc<Mutex<Script>>,
+    dirty: Arc<AtomicBool>,
+    stop: Arc<AtomicBool>,
+    runtime_dir: PathBuf,
+    thread: Option<JoinHandle<()>>,
+}
+
+static MOCK_COUNT: AtomicUsize = AtomicUsize::new(0);
+
+const SOCKET_NAME: &str = "wayland-mock";
+
+impl Mock {
+    pub fn start(script: Script) -> Self {
+        let runtime_dir = std::env::temp_dir().join(format!(
+            "flow-test-{}-{}",
+            std::process::id(),
+            MOCK_COUNT.fetch_add(1, Ordering::SeqCst)
+        ));
+        std::fs::create_dir_all(&runtime_dir).expect("Failed to create the runtime dir");
+
+        let socket = ListeningSocket::bind_absolute(runtime_dir.join(SOCKET_NAME))
+            .expect("Failed to bind the compositor socket");
+
+        let script = Arc::new(Mutex::new(script));
+        let dirty = Arc::new(AtomicBool::new(false));
+        let stop = Arc::new(AtomicBool::new(false));
+
+        let thread = {
+            let script = script.clone();
+            let dirty = dirty.clone();
+            let stop = stop.clone();
+            thread::spawn(move || run(socket, script, dirty, stop))
+        };
+
+        Self {
+            script,
+            dirty,
+            stop,
+            runtime_dir,
+            thread: Some(thread),
+        }
+    }
+
+    /// A flow command set up to talk to this compositor
+    pub fn command(&self, args: &[&str]) -> Command {
+        let mut command = Command::new(env!("CARGO_BIN_EXE_flow"));
+        command
+            .args(args)
+            .env("XDG_RUNTIME_DIR", &self.runtime_dir)
+            .env("XDG_CONFIG_HOME", self.runtime_dir.join("config"))
+            .env("WAYLAND_DISPLAY", SOCKET_NAME);
+        command
+    }
+
+    /// Run flow to completion
+    pub fn flow(&self, args: &[&str]) -> Output {
+        self.command(args).output().expect("Failed to run flow")
+    }
+
+    /// Start a flow daemon and wait until it's listening
+    pub fn daemon(&self) -> Child {
+        self.daemon_with(&[])
+    }
+
+    /// Start a flow daemon with extra arguments and wait until it's listening
+    pub fn daemon_with(&self, args: &[&str]) -> Child {
+        let daemon = self
+            .command(&[&["daemon"], args].concat())
+            .spawn()
+            .expect("Failed to start the daemon");
+
+        let socket = self.runtime_dir.join(format!("flow-{}.sock", SOCKET_NAME));
+        let start = Instant::now();
+        while !socket.exists() && start.elapsed() < Duration::from_secs(5) {
+            thread::sleep(Duration::from_millis(5));
+        }
+
+        daemon
+    }
+
+    /// Write the config file read by flow processes started from this mock
+    pub fn write_config(&self, contents: &str) {
+        let dir = self.runtime_dir.join("config").join("flow");
+        std::fs::create_dir_all(&dir).expect("Failed to create the config dir");
+        std::fs::write(dir.join("config.toml"), contents).expect("Failed to write the config");
+    }
+
+    pub fn runtime_dir(&self) -> &PathBuf {
+        &self.runtime_dir
+    }
+
+    /// The commands received so far
+    pub fn commands(&self) -> Vec<Vec<String>> {
+        self.script.lock().unwrap().commands.clone()
+    }
+
+    pub fn script(&self) -> Script {
+        self.script.lock().unwrap().clone()
+    }
+
+    /// Change the state and send it to every connected client
+    pub fn update(&self, change: impl FnOnce(&mut Script)) {
+        change(&mut self.script.lock().unwrap());
+        self.dirty.store(true, Ordering::SeqCst);
+    }
+
+    /// Wait until the given number of commands has been received, e.g. from a daemon
+    pub fn wait_for_commands(&self, count: usize) -> Vec<Vec<String>> {
+        let start = Instant::now();
+        while self.commands().len() < count && start.elapsed() < Duration::from_secs(5) {
+            thread::sleep(Duration::from_millis(5));
+        }
+        self.commands()
+    }
+}
+
+impl Drop for Mock {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::SeqCst);
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+        let _ = std::fs::remove_dir_all(&self.runtime_dir);
+    }
+}
+
+/// Two outputs, the first one focused on tag 1 with views on tags 1 and 3
+pub fn two_outputs() -> Script {
+    Script::new(vec![
+        MockOutput::new("DP-1", 0b1, &[0b1, 0b100]),
+        MockOutput::new("HDMI-A-1", 0b10, &[0b10]),
+    ])
+}
+
+/// Turn command output into lines for easier comparisons
+pub fn lines(bytes: &[u8]) -> Vec<String> {
+    String::from_utf8_lossy(bytes)
+        .lines()
+        .map(String::from)
+        .collect()
+}
+
+/// Shorthand for an expected command
+pub fn command(arguments: &[&str]) -> Vec<String> {
+    arguments
+        .iter()
+        .map(|argument| argument.to_string())
+        .collect()
+}
+
+struct ClientState;
+
+impl ClientData for ClientState {}
+
+struct Server {
+    script: Arc<Mutex<Script>>,
+    wl_outputs: Vec<WlOutput>,
+    output_statuses: Vec<ZriverOutputStatusV1>,
+    seat_statuses: Vec<ZriverSeatStatusV1>,
+    /// Arguments added to each control object since its last run_command
+    arguments: HashMap<ObjectId, Vec<String>>,
+    /// The layout name last sent to each output status object, as river only sends it when it changes
+    sent_layouts: HashMap<ObjectId, Option<String>>,
+    /// When the delayed output status is due
+    status_due: Option<Instant>,
+}
+
+fn run(
+    socket: ListeningSocket,
+    script: Arc<Mutex<Script>>,
+    dirty: Arc<AtomicBool>,
+    stop: Arc<AtomicBool>,
+) {
+    let mut display: Display<Server> = Display::new().expect("Failed to create the display");
+    let handle = display.handle();
+
+    let (output_count, seat_count) = {
+        let script = script.lock().unwrap();
+        (script.outputs.len(), script.other_seats.len() + 1)
+    };
+    for index in 0..output_count {
+        handle.create_global::<Server, WlOutput, usize>(4, index);
+    }
+    for index in 0..seat_count {
+        handle.create_global::<Server, WlSeat, usize>(7, index);
+    }
+    handle.create_global::<Server, ZriverStatusManagerV1, ()>(4, ());
+    handle.create_global::<Server, ZriverControlV1, ()>(1, ());
+
+    let mut server = Server {
+        script,
+        wl_outputs: vec![],
+        output_statuses: vec![],
+        seat_statuses: vec![],
+        arguments: HashMap::new(),
+        sent_layouts: HashMap::new(),
+        status_due: None,
+    };
+
+    while !stop.load(Ordering::SeqCst) {
+        if let Ok(Some(stream)) = socket.accept() {
+            let _ = display
+                .handle()
+                .insert_client(stream, Arc::new(ClientState));
+        }
+
+        let _ = display.dispatch_clients(&mut server);
+
+        let due = server.status_due.is_some_and(|due| due <= Instant::now());
+        if dirty.swap(false, Ordering::SeqCst) || due {
+            server.status_due = None;
+            server.send_all();
+        }
+
+        let _ = display.flush_clients();
+        thread::sleep(Duration::from_millis(1));
+    }
+}
+
+impl Server {
+    /// Send the current state to every status object
+    fn send_all(&mut self) {
+        self.output_statuses.retain(|status| status.is_alive());
+        self.seat_statuses.retain(|status| status.is_alive());
+        self.wl_outputs.retain(|output| output.is_alive());
+
+        self.sent_layouts
+            .retain(|id, _| self.output_statuses.iter().any(|status| &status.id() == id));
+
+        for status in self.output_statuses.clone() {
+            self.send_output_status(&status);
+        }
+        for status in &self.seat_statuses {
+            self.send_seat_status(status);
+        }
+    }
+
+    fn send_output_status(&mut self, status: &ZriverOutputStatusV1) {
+        let script = self.script.lock().unwrap();
+        let Some(output) = status
+            .data::<usize>()
+            .and_then(|index| script.outputs.get(*index))
+        else {
+            return;
+        };
+
+        let view_tags = output
+            .view_tags
+            .iter()
+            .flat_map(|tags| tags.to_ne_bytes())
+            .collect();
+
+        status.view_tags(view_tags);
+        status.focused_tags(output.focused_tags);
+        status.urgent_tags(output.urgent_tags);
+
+        let sent_layout = self.sent_layouts.entry(status.id()).or_default();
+        if *sent_layout != output.layout {
+            match &output.layout {
+                Some(layout) => status.layout_name(layout.to_owned()),
+                None => status.layout_name_clear(),
+            }
+            *sent_layout = output.layout.clone();
+        }
+    }
+
+    fn send_seat_status(&self, status: &ZriverSeatStatusV1) {
+        let mut script = self.script.lock().unwrap();
+        let seat = status.data::<usize>().copied().unwrap_or_default();
+        let focused_output = *script.seat_focus(seat);
+        let client = status.client().map(|client| client.id());
+
+        // Only the wl_output objects of the same client can be referred to
+        for wl_output in &self.wl_outputs {
+            if wl_output.client().map(|client| client.id()) != client {
+                continue;
+            }
+            match wl_output.data::<usize>() {
+                Some(&index) if index == focused_output => status.focused_output(wl_output),
+                Some(_) => status.unfocused_output(wl_output),
+                None => (),
+            }
+        }
+
+        status.focused_view(script.focused_view.to_owned());
+        status.mode(script.mode.to_owned());
+    }
+}
+
+impl GlobalDispatch<WlOutput, usize> for Server {
+    fn bind(
+        state: &mut Self,
+        _: &DisplayHandle,
+        _: &Client,
+        resource: New<WlOutput>,
+        index: &usize,
+        data_init: &mut DataInit<'_, Self>,
+    ) {
+        let output = data_init.init(resource, *index);
+        let name = state.script.lock().unwrap().outputs[*index].name.to_owned();
+        output.name(name);
+        output.done();
+        state.wl_outputs.push(output);
+    }
+}
+
+impl Dispatch<WlOutput, usize> for Server {
+    fn request(
+        _: &mut Self,
+        _: &Client,
+        _: &WlOutput,
+        _: <WlOutput as Resource>::Request,
+        _: &usize,
+        _: &DisplayHandle,
+        _: &mut DataInit<'_, Self>,
+    ) {
+    }
+}
+
+impl GlobalDispatch<WlSeat, usize> for Server {
+    fn bind(
+        state: &mut Self,
+        _: &DisplayHandle,
+        _: &Client,
+        resource: New<WlSeat>,
+        index: &usize,
+        data_init: &mut DataInit<'_, Self>,
+    ) {
+        let seat = data_init.init(resource, *index);
+        seat.name(state.script.lock().unwrap().seat_name(*index));
+    }
+}
+
+impl Dispatch<WlSeat, usize> for Server {
+    fn request(
+        _: &mut Self,
+        _: &Client,
+        _: &WlSeat,
+        _: <WlSeat as Resource>::Request,
+        _: &usize,
+        _: &DisplayHandle,
+        _: &mut DataInit<'_, Self>,
+    ) {
+    }
+}
+
+impl GlobalDispatch<ZriverStatusManagerV1, ()> for Server {
+    fn bind(
+        _: &mut Self,
+        _: &DisplayHandle,
+        _: &Client,
+        resource: New<ZriverStatusManagerV1>,
+        _: &(),
+        data_init: &mut DataInit<'_, Self>,
+    ) {
+        data_init.init(resource, ());
+    }
+}
+
+impl Dispatch<ZriverStatusManagerV1, ()> for Server {
+    fn request(
+        state: &mut Self,
+        _: &Client,
+        _: &ZriverStatusManagerV1,
+        request: <ZriverStatusManagerV1 as Resource>::Request,
+        _: &(),
+        _: &DisplayHandle,
+        data_init: &mut DataInit<'_, Self>,
+    ) {
+        match request {
+            zriver_status_manager_v1::Request::GetRiverOutputStatus { id, output } => {
+                let index = output.data::<usize>().copied().unwrap_or_default();
+                let status = data_init.init(id, index);
+                state.send_output_status(&status);
+                state.output_statuses.push(status);
+            }
+            zriver_status_manager_v1::Request::GetRiverSeatStatus { id, seat } => {
+                let index = seat.data::<usize>().copied().unwrap_or_default();
+                let status = data_init.init(id, index);
+                state.send_seat_status(&status);
+                state.seat_statuses.push(status);
+            }
+            _ => (),
+        }
+    }
+}
+
+impl Dispatch<ZriverOutputStatusV1, usize> for Server {
+    fn request(
+        _: &mut Self,
+        _: &Client,
+        _: &ZriverOutputStatusV1,
+        _: zriver_output_status_v1::Request,
+        _: &usize,
+        _: &DisplayHandle,
+        _: &mut DataInit<'_, Self>,
+    ) {
+    }
+}
+
+impl Dispatch<ZriverSeatStatusV1, usize> for Server {
+    fn request(
+        _: &mut Self,
+        _: &Client,
+        _: &ZriverSeatStatusV1,
+        _: <ZriverSeatStatusV1 as Resource>::Request,
+        _: &usize,
+        _: &DisplayHandle,
+        _: &mut DataInit<'_, Self>,
+    ) {
+    }
+}
+
+impl GlobalDispatch<ZriverControlV1, ()> for Server {
+    fn bind(
+        _: &mut Self,
+        _: &DisplayHandle,
+        _: &Client,
+        resource: New<ZriverControlV1>,
+        _: &(),
+        data_init: &mut DataInit<'_, Self>,
+    ) {
+        data_init.init(resource, ());
+    }
+}
+
+impl Dispatch<ZriverControlV1, ()> for Server {
+    fn request(
+        state: &mut Self,
+        _: &Client,
+        control: &ZriverControlV1,
+        request: zriver_control_v1::Request,
+        _: &(),
+        _: &DisplayHandle,
+        data_init: &mut DataInit<'_, Self>,
+    ) {
+        match request {
+            zriver_control_v1::Request::AddArgument { argument } => {
+                state
+                    .arguments
+                    .entry(control.id())
+                    .or_default()
+                    .push(argument);
+            }
+            zriver_control_v1::Request::RunCommand { seat, callback } => {
+                let arguments = state.arguments.remove(&control.id()).unwrap_or_default();
+                let callback = data_init.init(callback, ());
+                let seat = seat.data::<usize>().copied().unwrap_or_default();
+
+                let mut script = state.script.lock().unwrap();
+                script.commands.push(arguments.clone());
+                let seat_name = script.seat_name(seat);
+                script.command_seats.push(seat_name);
+
+                let name = arguments.first().cloned().unwrap_or_default();
+                if script.failing_commands.contains(&name) {
+                    callback.failure(format!("{} failed", name));
+                    return;
+                }
+
+                script.apply(&arguments, seat);
+                callback.success(
+                    script
+                        .command_output
+                        .get(&name)
+                        .cloned()
+                        .unwrap_or_default(),
+                );
+                let status_delay = script.status_delay;
+                drop(script);
+
+                match status_delay {
+                    Some(delay) => {
+                        state.status_due = Some(Instant::now() + delay);
+                        for status in &state.seat_statuses {
+                            state.send_seat_status(status);
+                        }
+                    }
+                    None => state.send_all(),
+                }
+            }
+            _ => (),
+        }
+    }
+
+    fn destroyed(state: &mut Self, _: ClientId, control: &ZriverControlV1, _: &()) {
+        state.arguments.remove(&control.id());
+    }
+}
+
+impl Dispatch<ZriverCommandCallbackV1, ()> for Server {
+    fn request(
+        _: &mut Self,
+        _: &Client,
+        _: &ZriverCommandCallbackV1,
+        _: <ZriverCommandCallbackV1 as Resource>::Request,
+        _: &(),
+        _: &DisplayHandle,
+        _: &mut DataInit<'_, Self>,
+    ) {
+    }
+}
@@ -0,0 +1,237 @@
+mod common;
+
+use std::io::{BufRead, BufReader};
+use std::process::Stdio;
+
+use common::{Mock, command, lines, two_outputs};
+
+#[test]
+fn tag_count_from_config() {
+    let mock = Mock::start(two_outputs());
+    mock.write_config("tags = 4");
+
+    mock.flow(&["cycle-tags", "previous"]);
+    // The command line takes precedence, otherwise this would wrap around to the first tag
+    mock.flow(&["cycle-tags", "next", "8"]);
+
+    assert_eq!(
+        mock.commands(),
+        vec![
+            command(&["set-focused-tags", "8"]),
+            command(&["set-focused-tags", "16"]),
+        ]
+    );
+}
+
+#[test]
+fn tag_count_per_output() {
+    let mock = Mock::start(two_outputs());
+    mock.write_config(
+        r#"
+tags = 4
+
+[outputs.DP-1]
+tags = 6
+"#,
+    );
+
+    mock.flow(&["cycle-tags", "previous"]);
+    mock.flow(&["toggle-tags", "~focused"]);
+
+    assert_eq!(
+        mock.commands(),
+        vec![
+            command(&["set-focused-tags", "32"]),
+            command(&["set-focused-tags", "31"]),
+        ]
+    );
+}
+
+#[test]
+fn cycle_tags_defaults_from_config() {
+    let mock = Mock::start(two_outputs());
+    mock.write_config(
+        r#"
+[cycle-tags]
+occupied = true
+"#,
+    );
+
+    mock.flow(&["cycle-tags", "next"]);
+    mock.flow(&["cycle-tags", "next", "--all"]);
+
+    assert_eq!(
+        mock.commands(),
+        vec![
+            command(&["set-focused-tags", "4"]),
+            command(&["set-focused-tags", "8"]),
+        ]
+    );
+}
+
+#[test]
+fn invalid_config_is_reported() {
+    let mock = Mock::start(two_outputs());
+
+    for config in [
+        "tags = 0",
+        "tags = 33",
+        "unknown = true",
+        "tag-names = [\"web\", \"web\"]",
+        "tag-names = [\"1st\"]",
+        "tag-names = [\"a-b\"]",
+        "tag-names = [\"focused\"]",
+        "[cycle-tags]\noccupied = 1",
+        "[scratchpads]\nterm = 0",
+        "[scratchpads]\nterm = 20\nmusic = 20",
+    ] {
+        mock.write_config(config);
+
+        let output = mock.flow(&["cycle-tags", "next"]);
+
+        assert!(!output.status.success(), "{} should be rejected", config);
+        assert!(
+            lines(&output.stderr)[0].starts_with("Error: Invalid config in"),
+            "{:?}",
+            lines(&output.stderr)
+        );
+    }
+
+    assert!(mock.commands().is_empty());
+}
+
+#[test]
+fn occupied_and_all_conflict() {
+    let mock = Mock::start(two_outputs());
+
+    assert!(
+        !mock
+            .flow(&["cycle-tags", "next", "--occupied", "--all"])
+            .status
+            .success()
+    );
+}
+
+#[test]
+fn named_tags() {
+    let mock = Mock::start(two_outputs());
+    mock.write_config(r#"tag-names = ["web", "mail", "chat"]"#);
+
+    mock.flow(&["toggle-tags", "mail"]);
+    mock.flow(&["focus-set-view-tags", "web,chat,5"]);
+    mock.flow(&["toggle-tags", "~web"]);
+
+    assert_eq!(
+        mock.commands(),
+        vec![
+            command(&["set-focused-tags", "2"]),
+            command(&["set-view-tags", "21"]),
+            command(&["set-focused-tags", "21"]),
+            command(&["set-focused-tags", "510"]),
+        ]
+    );
+
+    let output = mock.flow(&["toggle-tags", "music"]);
+    assert!(!output.status.success());
+    assert_eq!(lines(&output.stderr), vec!["Error: Unknown tags: music"]);
+}
+
+#[test]
+fn watch_shows_tag_names() {
+    let mock = Mock::start(two_outputs());
+    mock.write_config(r#"tag-names = ["web", "mail"]"#);
+
+    let mut watch = mock
+        .command(&["watch"])
+        .stdout(Stdio::piped())
+        .spawn()
+        .expect("Failed to start watch");
+    let first_line = BufReader::new(watch.stdout.take().unwrap())
+        .lines()
+        .next()
+        .unwrap()
+        .unwrap();
+
+    assert!(
+        first_line.contains(r#""focused_tag_names":["web"],"occupied_tag_names":["web","3"]"#),
+        "{}",
+        first_line
+    );
+
+    watch.kill().unwrap();
+    watch.wait().unwrap();
+
+    let output = mock.flow(&["query", "--json"]);
+    assert!(output.status.success());
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(
+        stdout.contains(r#""focused_tag_names":["web"],"occupied_tag_names":["web","3"]"#),
+        "{}",
+        stdout
+    );
+}
+
+#[test]
+fn scratchpads() {
+    let mock = Mock::start(two_outputs());
+    mock.write_config(
+        r#"
+[scratchpads]
+term = 20
+music = 21
+"#,
+    );
+
+    for args in [
+        &["scratchpad", "init"][..],
+        &["scratchpad", "send", "term"],
+        &["scratchpad", "toggle", "term"],
+        &["scratchpad", "toggle", "term"],
+    ] {
+        assert!(
+            mock.flow(args).status.success(),
+            "{:?} should succeed",
+            args
+        );
+    }
+
+    // Nothing to show
+    let output = mock.flow(&["scratchpad", "toggle", "music"]);
+    assert_eq!(
+        lines(&output.stderr),
+        vec!["Error: The scratchpad doesn't hold any views"]
+    );
+    for args in [
+        &["scratchpad", "toggle"][..],
+        &["scratchpad", "toggle", "notes"],
+        &["scratchpad", "open", "term"],
+    ] {
+        assert!(!mock.flow(args).status.success(), "{:?} should fail", args);
+    }
+
+    assert_eq!(
+        mock.commands(),
+        vec![
+            command(&["spawn-tagmask", "4293394431"]),
+            command(&["set-view-tags", "524288"]),
+            command(&["set-focused-tags", "524289"]),
+            command(&["set-focused-tags", "1"]),
+        ]
+    );
+}
+
+#[test]
+fn default_scratchpad() {
+    let mut script = two_outputs();
+    script.outputs[0].focused_tags = 1 << 20;
+    script.outputs[0].view_tags = vec![1 << 20];
+    script.outputs[0].previous_tags = 0b10;
+    let mock = Mock::start(script);
+
+    assert!(mock.flow(&["scratchpad", "toggle"]).status.success());
+
+    mock.write_config("[scratchpads]\nterm = 22");
+    assert!(!mock.flow(&["scratchpad", "toggle"]).status.success());
+
+    assert_eq!(mock.commands(), vec![command(&["focus-previous-tags"])]);
+}